@@ -0,0 +1,67 @@
+use std::fmt;
+use agenda_client::ClientError;
+
+
+#[derive(Debug)]
+pub enum CliError {
+    // The server, or the connection to it, failed the call
+    Client{error: ClientError},
+    // Files which cannot be read or written
+    Io{error: String},
+    // Arguments or file contents which are not valid
+    Input{error: String},
+}
+
+// Calls failed with a gRPC code exit with 64 plus the code, the same as grpcurl, so scripts can tell
+// a missing agenda (69) from an unreachable server (78)
+const STATUS_EXIT_CODE_BASE: i32 = 64;
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Client{error} => STATUS_EXIT_CODE_BASE + error.code() as i32,
+            CliError::Io{..} => 1,
+            // Same as clap for invalid arguments
+            CliError::Input{..} => 2,
+        }
+    }
+}
+
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Client{error} => write!(f, "{} ({:?})", error, error.code()),
+            CliError::Io{error} => write!(f, "{}", error),
+            CliError::Input{error} => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<ClientError> for CliError {
+    fn from(error: ClientError) -> Self {
+        CliError::Client{error}
+    }
+}
+
+impl From<std::io::Error> for CliError {
+    fn from(err: std::io::Error) -> Self {
+        CliError::Io{error: err.to_string()}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_exit_code() {
+        let not_found = CliError::from(ClientError::NotFound{message: "missing".to_string()});
+        assert_eq!(not_found.exit_code(), 69);
+        assert_eq!(not_found.to_string(), "missing (NotFound)");
+        assert_eq!(CliError::from(ClientError::Connection{error: "refused".to_string()}).exit_code(), 78);
+        assert_eq!(CliError::from(std::io::Error::other("denied")).exit_code(), 1);
+        assert_eq!(CliError::Input{error: "invalid".to_string()}.exit_code(), 2);
+    }
+}
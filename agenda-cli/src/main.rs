@@ -0,0 +1,247 @@
+mod error;
+mod output;
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+use agenda_client::agenda::Agenda;
+use agenda_client::{AgendaClient, ClientConfig, ClientError, RetryPolicy, TlsConfig};
+use clap::{Args, Parser, Subcommand};
+use crate::error::CliError;
+use crate::output::{read_agendas, write_agenda, write_agendas, Format};
+
+
+#[derive(Debug, Parser)]
+#[command(name = "agenda-cli", version, about = "Calls the agenda service")]
+struct Cli {
+    #[arg(long, env = "AGENDA_ENDPOINT", default_value = "http://localhost:50051", global = true)]
+    endpoint: String,
+    #[arg(long, env = "AGENDA_API_KEY", hide_env_values = true, global = true, help = "Sent as x-api-key")]
+    api_key: Option<String>,
+    #[arg(long, env = "AGENDA_ACTOR", global = true, help = "Sent as x-actor, recorded in the agenda history next to the peer address when no API key is used")]
+    actor: Option<String>,
+    #[command(flatten)]
+    tls: TlsArgs,
+    #[arg(long, env = "AGENDA_TIMEOUT_MS", default_value_t = 30000, global = true, help = "Deadline of each call, 0 for none")]
+    timeout_ms: u64,
+    #[arg(long, env = "AGENDA_RETRIES", default_value_t = 3, global = true, help = "Attempts of calls the server answers with Unavailable")]
+    attempts: u32,
+    #[arg(long, short, value_enum, default_value_t = Format::Table, global = true)]
+    output: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Args)]
+struct TlsArgs {
+    #[arg(long, env = "AGENDA_TLS_CA", global = true, help = "Enables TLS with this authority instead of the system roots, https endpoints always use TLS")]
+    tls_ca: Option<PathBuf>,
+    #[arg(long, env = "AGENDA_TLS_DOMAIN", global = true, help = "Name expected in the server's certificate, when it differs from the endpoint's host")]
+    tls_domain: Option<String>,
+    #[arg(long, env = "AGENDA_TLS_CERT", requires = "tls_key", global = true)]
+    tls_cert: Option<PathBuf>,
+    #[arg(long, env = "AGENDA_TLS_KEY", requires = "tls_cert", global = true)]
+    tls_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct AgendaArgs {
+    #[arg(long)]
+    name: String,
+    #[arg(long)]
+    email: String,
+    #[arg(long)]
+    phone: String,
+}
+
+impl From<AgendaArgs> for Agenda {
+    fn from(args: AgendaArgs) -> Self {
+        Agenda {id: 0, name: args.name, email: args.email, phone: args.phone}
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(about = "Checks the server answers")]
+    Ping,
+    Get {
+        id: i64,
+    },
+    #[command(about = "Every agenda, fetched a page at a time")]
+    List {
+        #[arg(long, default_value_t = 100)]
+        page_size: i64,
+    },
+    Create {
+        #[command(flatten)]
+        agenda: AgendaArgs,
+    },
+    Update {
+        id: i64,
+        #[command(flatten)]
+        agenda: AgendaArgs,
+    },
+    Delete {
+        id: i64,
+    },
+    #[command(about = "Creates the agendas of a JSON or CSV file, reporting the records which fail")]
+    Import {
+        file: PathBuf,
+        #[arg(long, value_enum, help = "Taken from the file's extension when not given")]
+        format: Option<Format>,
+    },
+    #[command(about = "Writes every agenda to a JSON or CSV file, or to the standard output")]
+    Export {
+        file: Option<PathBuf>,
+        #[arg(long, value_enum, help = "Taken from the file's extension, or --output, when not given")]
+        format: Option<Format>,
+        #[arg(long, default_value_t = 100)]
+        page_size: i64,
+    },
+}
+
+
+fn read_file(path: &PathBuf) -> Result<Vec<u8>, CliError> {
+    std::fs::read(path).map_err(|err| CliError::Io{error: format!("{}: {err}", path.display())})
+}
+
+fn file_format(file: Option<&PathBuf>, format: Option<Format>, default: Format) -> Format {
+    format
+        .or_else(|| file.and_then(|file| Format::from_path(&file.to_string_lossy())))
+        .unwrap_or(default)
+}
+
+fn client_config(cli: &Cli) -> Result<ClientConfig, CliError> {
+    let tls_enabled = cli.tls.tls_ca.is_some() || cli.tls.tls_domain.is_some() || cli.tls.tls_cert.is_some();
+    let tls = tls_enabled.then(|| -> Result<TlsConfig, CliError> {
+        let identity = match (&cli.tls.tls_cert, &cli.tls.tls_key) {
+            (Some(certificate), Some(key)) => Some((read_file(certificate)?, read_file(key)?)),
+            _ => None,
+        };
+        Ok(TlsConfig {
+            ca_certificate: cli.tls.tls_ca.as_ref().map(read_file).transpose()?,
+            domain: cli.tls.tls_domain.clone(),
+            identity,
+        })
+    }).transpose()?;
+
+    Ok(ClientConfig {
+        tls,
+        api_key: cli.api_key.clone(),
+        actor: cli.actor.clone(),
+        timeout: (cli.timeout_ms > 0).then(|| Duration::from_millis(cli.timeout_ms)),
+        retry: RetryPolicy {max_attempts: cli.attempts.max(1), ..RetryPolicy::default()},
+        ..ClientConfig::new(cli.endpoint.clone())
+    })
+}
+
+async fn all_agendas(client: &AgendaClient, page_size: i64) -> Result<Vec<Agenda>, ClientError> {
+    if page_size < 1 {
+        return Err(ClientError::Config{error: "the page size must be at least 1".to_string()});
+    }
+    client.pages(page_size).collect().await
+}
+
+async fn run(cli: Cli) -> Result<(), CliError> {
+    let client = AgendaClient::connect_lazy(client_config(&cli)?)?;
+    let mut stdout = std::io::stdout().lock();
+
+    match cli.command {
+        Command::Ping => {
+            let response = client.ping().await?;
+            match cli.output {
+                Format::Json => writeln!(stdout, "{}", serde_json::json!({"response": response}))?,
+                _ => writeln!(stdout, "{response}")?,
+            }
+        }
+        Command::Get {id} => write_agenda(cli.output, &client.get_agenda(id).await?, &mut stdout)?,
+        Command::List {page_size} => write_agendas(cli.output, &all_agendas(&client, page_size).await?, &mut stdout)?,
+        Command::Create {agenda} => write_agenda(cli.output, &client.create_agenda(agenda.into()).await?, &mut stdout)?,
+        Command::Update {id, agenda} => write_agenda(cli.output, &client.update_agenda(id, agenda.into()).await?, &mut stdout)?,
+        Command::Delete {id} => client.delete_agenda(id).await?,
+        Command::Import {file, format} => {
+            let format = format.or_else(|| Format::from_path(&file.to_string_lossy()))
+                .ok_or_else(|| CliError::Input{error: format!("{}: unknown format, use --format", file.display())})?;
+            let records = read_agendas(format, read_file(&file)?.as_slice())?;
+
+            // Every record is tried, the last failure gives the exit code
+            let mut created = 0;
+            let mut failure = None;
+            for (record, agenda) in records {
+                let result = match agenda {
+                    Ok(agenda) => client.create_agenda(agenda).await.map_err(CliError::from),
+                    Err(error) => Err(CliError::Input{error}),
+                };
+                match result {
+                    Ok(_) => created += 1,
+                    Err(err) => {
+                        eprintln!("record {record}: {err}");
+                        failure = Some(err);
+                    }
+                }
+            }
+            eprintln!("{created} agendas imported");
+            if let Some(failure) = failure {
+                return Err(failure);
+            }
+        }
+        Command::Export {file, format, page_size} => {
+            let format = file_format(file.as_ref(), format, cli.output);
+            let agendas = all_agendas(&client, page_size).await?;
+            match file {
+                Some(file) => {
+                    let writer = std::fs::File::create(&file)
+                        .map_err(|err| CliError::Io{error: format!("{}: {err}", file.display())})?;
+                    write_agendas(format, &agendas, std::io::BufWriter::new(writer))?;
+                    eprintln!("{} agendas exported to {}", agendas.len(), file.display());
+                }
+                None => write_agendas(format, &agendas, &mut stdout)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(err) = run(cli).await {
+        eprintln!("error: {err}");
+        std::process::exit(err.exit_code());
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["agenda-cli", "--endpoint", "https://agenda.example.com", "-o", "csv", "update", "7", "--name", "Ada", "--email", "a", "--phone", "5"]).unwrap();
+        assert_eq!(cli.output, Format::Csv);
+        assert!(matches!(cli.command, Command::Update {id: 7, ..}));
+        let config = client_config(&cli).unwrap();
+        assert_eq!(config.endpoint, "https://agenda.example.com");
+        assert!(config.tls.is_none());
+
+        let cli = Cli::try_parse_from(["agenda-cli", "list", "--timeout-ms", "0", "--tls-domain", "agenda.internal"]).unwrap();
+        let config = client_config(&cli).unwrap();
+        assert!(config.timeout.is_none());
+        assert_eq!(config.tls.unwrap().domain.as_deref(), Some("agenda.internal"));
+
+        assert!(Cli::try_parse_from(["agenda-cli", "get"]).is_err());
+        assert!(Cli::try_parse_from(["agenda-cli", "list", "--tls-cert", "cert.pem"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_format() {
+        let file = PathBuf::from("agendas.csv");
+        assert_eq!(file_format(Some(&file), None, Format::Table), Format::Csv);
+        assert_eq!(file_format(Some(&file), Some(Format::Json), Format::Table), Format::Json);
+        assert_eq!(file_format(None, None, Format::Table), Format::Table);
+    }
+}
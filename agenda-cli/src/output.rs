@@ -0,0 +1,176 @@
+use std::io::{Read, Write};
+use agenda_client::agenda::Agenda;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use crate::error::CliError;
+
+const COLUMNS: [&str; 4] = ["id", "name", "email", "phone"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl Format {
+    // Files are read and written as JSON or CSV depending on their extension when no format is given
+    pub fn from_path(path: &str) -> Option<Format> {
+        match path.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).as_deref() {
+            Some("json") => Some(Format::Json),
+            Some("csv") => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+
+fn csv_error(err: csv::Error) -> CliError {
+    match err.into_kind() {
+        csv::ErrorKind::Io(err) => CliError::from(err),
+        kind => CliError::Input{error: format!("invalid CSV: {kind:?}")},
+    }
+}
+
+fn agenda_json(agenda: &Agenda) -> Value {
+    json!({"id": agenda.id, "name": agenda.name, "email": agenda.email, "phone": agenda.phone})
+}
+
+pub fn write_agendas(format: Format, agendas: &[Agenda], mut writer: impl Write) -> Result<(), CliError> {
+    match format {
+        Format::Table => {
+            let rows = agendas.iter()
+                .map(|agenda| [agenda.id.to_string(), agenda.name.clone(), agenda.email.clone(), agenda.phone.clone()])
+                .collect::<Vec<[String; 4]>>();
+            let widths = (0..COLUMNS.len())
+                .map(|column| rows.iter().map(|row| row[column].chars().count()).chain([COLUMNS[column].len()]).max().unwrap_or(0))
+                .collect::<Vec<usize>>();
+            let header = COLUMNS.map(|column| column.to_uppercase());
+            for row in [header].iter().chain(rows.iter()) {
+                let cells = row.iter().zip(&widths).map(|(cell, width)| format!("{cell:width$}")).collect::<Vec<String>>();
+                writeln!(writer, "{}", cells.join("  ").trim_end())?;
+            }
+        }
+        Format::Json => {
+            let agendas = agendas.iter().map(agenda_json).collect::<Vec<Value>>();
+            serde_json::to_writer_pretty(&mut writer, &agendas).map_err(|err| CliError::Io{error: err.to_string()})?;
+            writeln!(writer)?;
+        }
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(writer);
+            csv.write_record(COLUMNS).map_err(csv_error)?;
+            for agenda in agendas {
+                csv.write_record([agenda.id.to_string(), agenda.name.clone(), agenda.email.clone(), agenda.phone.clone()]).map_err(csv_error)?;
+            }
+            csv.flush()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_agenda(format: Format, agenda: &Agenda, mut writer: impl Write) -> Result<(), CliError> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut writer, &agenda_json(agenda)).map_err(|err| CliError::Io{error: err.to_string()})?;
+            writeln!(writer)?;
+            Ok(())
+        }
+        format => write_agendas(format, std::slice::from_ref(agenda), writer),
+    }
+}
+
+
+// One result per record, numbered from 1, so a bad row does not stop the others from being imported
+pub type Record = (usize, Result<Agenda, String>);
+
+pub fn read_agendas(format: Format, reader: impl Read) -> Result<Vec<Record>, CliError> {
+    match format {
+        Format::Table => Err(CliError::Input{error: "agendas can only be imported from JSON or CSV".to_string()}),
+        Format::Json => {
+            let values: Vec<Value> = serde_json::from_reader(reader).map_err(|err| CliError::Input{error: format!("invalid JSON: {err}")})?;
+            Ok(values.iter().enumerate().map(|(index, value)| {
+                let field = |name: &str| value.get(name).and_then(|field| field.as_str()).map(|field| field.to_string())
+                    .ok_or(format!("missing {name}"));
+                let agenda = field("name")
+                    .and_then(|name| Ok(Agenda {id: 0, name, email: field("email")?, phone: field("phone")?}));
+                (index + 1, agenda)
+            }).collect())
+        }
+        Format::Csv => {
+            let mut csv = csv::ReaderBuilder::new().flexible(true).trim(csv::Trim::All).from_reader(reader);
+            // Columns are found by name, in any order and any case, other columns are ignored
+            let headers = csv.headers().map_err(csv_error)?.clone();
+            let column = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name))
+                .ok_or_else(|| CliError::Input{error: format!("the CSV header has no {name} column")});
+            let (name, email, phone) = (column("name")?, column("email")?, column("phone")?);
+
+            Ok(csv.records().enumerate().map(|(index, record)| {
+                let agenda = record.map_err(|err| err.to_string()).and_then(|record| {
+                    let field = |position: usize, name: &str| record.get(position).map(|field| field.to_string()).ok_or(format!("missing {name}"));
+                    Ok(Agenda {id: 0, name: field(name, "name")?, email: field(email, "email")?, phone: field(phone, "phone")?})
+                });
+                (index + 1, agenda)
+            }).collect())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agendas() -> Vec<Agenda> {
+        vec![
+            Agenda {id: 1, name: "Ada".to_string(), email: "ada@example.com".to_string(), phone: "555".to_string()},
+            Agenda {id: 12, name: "Grace, H".to_string(), email: "grace@example.com".to_string(), phone: "556".to_string()},
+        ]
+    }
+
+    fn written(format: Format) -> String {
+        let mut output = Vec::new();
+        write_agendas(format, &agendas(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_write_agendas() {
+        assert_eq!(written(Format::Table), "\
+ID  NAME      EMAIL              PHONE
+1   Ada       ada@example.com    555
+12  Grace, H  grace@example.com  556
+");
+        assert_eq!(written(Format::Csv), "id,name,email,phone\n1,Ada,ada@example.com,555\n12,\"Grace, H\",grace@example.com,556\n");
+        let json: Value = serde_json::from_str(&written(Format::Json)).unwrap();
+        assert_eq!(json[1]["id"], 12);
+        assert_eq!(json[1]["name"], "Grace, H");
+    }
+
+    #[tokio::test]
+    async fn test_read_agendas() {
+        for format in [Format::Csv, Format::Json] {
+            let records = read_agendas(format, written(format).as_bytes()).unwrap();
+            let imported = records.into_iter().map(|(_, agenda)| agenda.unwrap()).collect::<Vec<Agenda>>();
+            assert_eq!(imported.iter().map(|agenda| agenda.name.as_str()).collect::<Vec<&str>>(), vec!["Ada", "Grace, H"]);
+            assert!(imported.iter().all(|agenda| agenda.id == 0));
+        }
+
+        let records = read_agendas(Format::Csv, "Phone,Name,EMAIL\n555,Ada,ada@example.com\n556,Grace\n".as_bytes()).unwrap();
+        assert_eq!(records[0].1.as_ref().unwrap().email, "ada@example.com");
+        assert_eq!(records[1], (2, Err("missing email".to_string())));
+
+        let records = read_agendas(Format::Json, r#"[{"name": "Ada", "email": "a"}]"#.as_bytes()).unwrap();
+        assert_eq!(records, vec![(1, Err("missing phone".to_string()))]);
+
+        assert!(matches!(read_agendas(Format::Csv, "name,email\n".as_bytes()), Err(CliError::Input{..})));
+        assert!(matches!(read_agendas(Format::Table, "".as_bytes()), Err(CliError::Input{..})));
+    }
+
+    #[tokio::test]
+    async fn test_format_from_path() {
+        assert_eq!(Format::from_path("agendas.CSV"), Some(Format::Csv));
+        assert_eq!(Format::from_path("backup/agendas.json"), Some(Format::Json));
+        assert_eq!(Format::from_path("agendas"), None);
+    }
+}
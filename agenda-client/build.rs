@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Compiled from the server's proto so both sides always share the same definitions, the server
+    // stubs are kept for services that want to fake the agenda service in their tests
+    tonic_build::configure()
+        .compile(
+            &["../src/proto/agenda/v1/agenda.proto"],
+            &["../src/proto/agenda/v1", "../src/proto"],
+        ).unwrap_or_else(
+        |e| panic!("Failed to compile proto file: {:?}", e)
+    );
+    Ok(())
+}
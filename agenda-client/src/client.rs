@@ -0,0 +1,476 @@
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
+use futures_util::stream;
+use tonic::{Code, Request, Response, Status};
+use tonic::metadata::{AsciiMetadataValue, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+use crate::agenda::agenda_service_client::AgendaServiceClient;
+use crate::agenda::{
+    Agenda, CreateAgendaRequest, DeleteAgendaRequest, ExportAgendasRequest, FileFormat, GetAgendaRequest,
+    GetAgendasRequest, GetAgendasResponse, ImportAgendasRequest, ImportAgendasResponse, ListAgendaHistoryRequest,
+    ListAgendaHistoryResponse, PingRequest, RestoreAgendaRequest, UpdateAgendaRequest,
+};
+use crate::config::ClientConfig;
+use crate::error::ClientError;
+use crate::pages::AgendaPages;
+use crate::retry::RetryPolicy;
+
+// Files are imported in messages of at most this many bytes
+const IMPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+
+// Adds the credentials to the metadata of every call
+#[derive(Debug, Clone)]
+struct CredentialsInterceptor {
+    api_key: Option<AsciiMetadataValue>,
+    actor: Option<AsciiMetadataValue>,
+}
+
+impl Interceptor for CredentialsInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(api_key) = &self.api_key {
+            request.metadata_mut().insert("x-api-key", api_key.clone());
+        }
+        if let Some(actor) = &self.actor {
+            request.metadata_mut().insert("x-actor", actor.clone());
+        }
+        Ok(request)
+    }
+}
+
+
+// Cheap to clone, clones share the same connection
+#[derive(Debug, Clone)]
+pub struct AgendaClient {
+    inner: AgendaServiceClient<InterceptedService<Channel, CredentialsInterceptor>>,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+}
+
+impl AgendaClient {
+    pub async fn connect(config: ClientConfig) -> Result<Self, ClientError> {
+        let channel = config.endpoint()?.connect().await?;
+        AgendaClient::with_channel(channel, config)
+    }
+
+    // Connects on the first call instead, so the server does not have to be up yet
+    pub fn connect_lazy(config: ClientConfig) -> Result<Self, ClientError> {
+        let channel = config.endpoint()?.connect_lazy();
+        AgendaClient::with_channel(channel, config)
+    }
+
+    pub fn with_channel(channel: Channel, config: ClientConfig) -> Result<Self, ClientError> {
+        let metadata = |name: &str, value: Option<String>| value
+            .map(|value| MetadataValue::try_from(value).map_err(|err| ClientError::Config{error: format!("invalid {name}: {err}")}))
+            .transpose();
+        let interceptor = CredentialsInterceptor {
+            api_key: metadata("API key", config.api_key)?,
+            actor: metadata("actor", config.actor)?,
+        };
+        Ok(AgendaClient {
+            inner: AgendaServiceClient::with_interceptor(channel, interceptor),
+            timeout: config.timeout,
+            retry: config.retry,
+        })
+    }
+
+    // Client whose calls, retries included, must finish within the timeout
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        AgendaClient {timeout: Some(timeout), ..self.clone()}
+    }
+
+    // Client whose calls must finish before the deadline, such as the one of the request being served
+    pub fn with_deadline(&self, deadline: Instant) -> Self {
+        self.with_timeout(deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        AgendaClient {retry, ..self.clone()}
+    }
+
+    // Calls again while the server is Unavailable, waiting at least what it asked for, until the
+    // attempts or the time run out
+    async fn call<M, R, F, Fut>(&self, message: M, rpc: F) -> Result<R, ClientError>
+    where
+        M: Clone,
+        F: Fn(AgendaServiceClient<InterceptedService<Channel, CredentialsInterceptor>>, Request<M>) -> Fut,
+        Fut: Future<Output = Result<Response<R>, Status>>,
+    {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut attempt = 1;
+        loop {
+            let mut request = Request::new(message.clone());
+            // Sent to the server and also enforced here, in case the server or the network does not answer
+            let result = match deadline {
+                Some(deadline) => {
+                    request.set_timeout(deadline.saturating_duration_since(Instant::now()));
+                    match tokio::time::timeout_at(deadline.into(), rpc(self.inner.clone(), request)).await {
+                        // The server gives up on the same deadline, and reports it as Cancelled
+                        Ok(Err(status)) if Instant::now() >= deadline => Err(Status::deadline_exceeded(status.message())),
+                        Ok(result) => result,
+                        Err(_) => Err(Status::deadline_exceeded("deadline exceeded before the server answered")),
+                    }
+                }
+                None => rpc(self.inner.clone(), request).await,
+            };
+            let status = match result {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status) => status,
+            };
+            if status.code() != Code::Unavailable || attempt >= self.retry.max_attempts {
+                return Err(status.into());
+            }
+
+            let error = ClientError::from(status);
+            let backoff = self.retry.backoff(attempt).max(error.retry_after().unwrap_or_default());
+            if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                return Err(error);
+            }
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    pub async fn ping(&self) -> Result<String, ClientError> {
+        self.call(PingRequest {}, |mut client, request| async move { client.ping(request).await })
+            .await
+            .map(|response| response.response)
+    }
+
+    pub async fn create_agenda(&self, agenda: Agenda) -> Result<Agenda, ClientError> {
+        let message = CreateAgendaRequest {agenda: Some(agenda)};
+        self.call(message, |mut client, request| async move { client.create_agenda(request).await })
+            .await
+            .and_then(|response| response.agenda.ok_or_else(missing_agenda))
+    }
+
+    pub async fn get_agenda(&self, id: i64) -> Result<Agenda, ClientError> {
+        let message = GetAgendaRequest {id, as_of: None};
+        self.call(message, |mut client, request| async move { client.get_agenda(request).await })
+            .await
+            .and_then(|response| response.agenda.ok_or_else(missing_agenda))
+    }
+
+    // The agenda as recorded in its history at that moment
+    pub async fn get_agenda_as_of(&self, id: i64, as_of: SystemTime) -> Result<Agenda, ClientError> {
+        let message = GetAgendaRequest {id, as_of: Some(as_of.into())};
+        self.call(message, |mut client, request| async move { client.get_agenda(request).await })
+            .await
+            .and_then(|response| response.agenda.ok_or_else(missing_agenda))
+    }
+
+    // Pages start at 1, the response's next_page is 0 after the last one
+    pub async fn get_agendas(&self, page: i64, items: i64) -> Result<GetAgendasResponse, ClientError> {
+        let message = GetAgendasRequest {page, items};
+        self.call(message, |mut client, request| async move { client.get_agendas(request).await }).await
+    }
+
+    // Every agenda, fetched items at a time
+    pub fn pages(&self, items: i64) -> AgendaPages {
+        AgendaPages::new(self.clone(), items)
+    }
+
+    pub async fn update_agenda(&self, id: i64, agenda: Agenda) -> Result<Agenda, ClientError> {
+        let message = UpdateAgendaRequest {id, agenda: Some(agenda), update_mask: None};
+        self.call(message, |mut client, request| async move { client.update_agenda(request).await })
+            .await
+            .and_then(|response| response.agenda.ok_or_else(missing_agenda))
+    }
+
+    pub async fn delete_agenda(&self, id: i64) -> Result<(), ClientError> {
+        let message = DeleteAgendaRequest {id};
+        self.call(message, |mut client, request| async move { client.delete_agenda(request).await })
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn list_agenda_history(&self, id: i64, page: i64, items: i64) -> Result<ListAgendaHistoryResponse, ClientError> {
+        let message = ListAgendaHistoryRequest {id, page, items};
+        self.call(message, |mut client, request| async move { client.list_agenda_history(request).await }).await
+    }
+
+    // Puts the agenda back as it was after the revision, see AgendaRevision
+    pub async fn restore_agenda(&self, id: i64, revision: i64) -> Result<Agenda, ClientError> {
+        let message = RestoreAgendaRequest {id, revision};
+        self.call(message, |mut client, request| async move { client.restore_agenda(request).await })
+            .await
+            .and_then(|response| response.agenda.ok_or_else(missing_agenda))
+    }
+
+    // The whole file, put together from the chunks the server streams
+    pub async fn export_agendas(&self, format: FileFormat) -> Result<Vec<u8>, ClientError> {
+        let message = ExportAgendasRequest {format: format.into()};
+        self.call(message, |mut client, request| async move {
+            let mut chunks = client.export_agendas(request).await?.into_inner();
+            let mut data = Vec::new();
+            while let Some(chunk) = chunks.message().await? {
+                data.extend(chunk.data);
+            }
+            Ok::<Response<Vec<u8>>, Status>(Response::new(data))
+        }).await
+    }
+
+    // The response lists the records of the file which could not be imported
+    pub async fn import_agendas(&self, format: FileFormat, data: Vec<u8>) -> Result<ImportAgendasResponse, ClientError> {
+        // Never retried, the server may have imported part of the file before it became unavailable
+        self.with_retry(RetryPolicy::disabled()).call(data, |mut client, request| async move {
+            let request = request.map(|data| {
+                let message = |data: &[u8]| ImportAgendasRequest {format: format.into(), data: data.to_vec()};
+                // An empty file is still one message, which carries the format
+                let mut messages = data.chunks(IMPORT_CHUNK_SIZE).map(message).collect::<Vec<ImportAgendasRequest>>();
+                if messages.is_empty() {
+                    messages.push(message(&[]));
+                }
+                stream::iter(messages)
+            });
+            client.import_agendas(request).await
+        }).await
+    }
+}
+
+fn missing_agenda() -> ClientError {
+    ClientError::Status{code: Code::Internal, message: "the response has no agenda".to_string()}
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tonic::transport::Server;
+    use tonic::transport::server::TcpIncoming;
+    use crate::agenda::agenda_service_server::{AgendaService, AgendaServiceServer};
+    use crate::agenda::*;
+    use super::*;
+
+    // Keeps agendas in memory, answers Unavailable to the first pings and records the API keys it sees
+    #[derive(Default)]
+    struct FakeAgendaService {
+        agendas: Mutex<Vec<Agenda>>,
+        unavailable_pings: AtomicU32,
+        unavailable_imports: AtomicU32,
+        pings: AtomicU32,
+        api_keys: Mutex<Vec<String>>,
+    }
+
+    impl FakeAgendaService {
+        fn find(&self, id: i64) -> Option<Agenda> {
+            self.agendas.lock().unwrap().iter().find(|agenda| agenda.id == id).cloned()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl AgendaService for FakeAgendaService {
+        async fn ping(&self, request: Request<PingRequest>) -> Result<Response<PingResponse>, Status> {
+            if let Some(api_key) = request.metadata().get("x-api-key") {
+                self.api_keys.lock().unwrap().push(api_key.to_str().unwrap().to_string());
+            }
+            self.pings.fetch_add(1, Ordering::SeqCst);
+            let unavailable = self.unavailable_pings.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1));
+            match unavailable {
+                Ok(_) => Err(Status::unavailable("overloaded")),
+                Err(_) => Ok(Response::new(PingResponse {response: "pong".to_string()})),
+            }
+        }
+
+        async fn create_agenda(&self, request: Request<CreateAgendaRequest>) -> Result<Response<CreateAgendaResponse>, Status> {
+            let mut agenda = request.into_inner().agenda.ok_or_else(|| Status::invalid_argument("missing agenda"))?;
+            let mut agendas = self.agendas.lock().unwrap();
+            if agendas.iter().any(|existing| existing.name == agenda.name) {
+                return Err(Status::already_exists(agenda.name));
+            }
+            agenda.id = agendas.len() as i64 + 1;
+            agendas.push(agenda.clone());
+            Ok(Response::new(CreateAgendaResponse {agenda: Some(agenda)}))
+        }
+
+        async fn get_agenda(&self, request: Request<GetAgendaRequest>) -> Result<Response<GetAgendaResponse>, Status> {
+            let agenda = self.find(request.into_inner().id).ok_or_else(|| Status::not_found("missing"))?;
+            Ok(Response::new(GetAgendaResponse {agenda: Some(agenda)}))
+        }
+
+        async fn get_agendas(&self, request: Request<GetAgendasRequest>) -> Result<Response<GetAgendasResponse>, Status> {
+            let message = request.into_inner();
+            if message.items == 0 {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            let agendas = self.agendas.lock().unwrap().clone();
+            let start = ((message.page - 1) * message.items) as usize;
+            let end = (start + message.items as usize).min(agendas.len());
+            Ok(Response::new(GetAgendasResponse {
+                agendas: agendas[start.min(end)..end].to_vec(),
+                total: agendas.len() as i64,
+                next_page: if end < agendas.len() { message.page + 1 } else { 0 },
+            }))
+        }
+
+        async fn update_agenda(&self, request: Request<UpdateAgendaRequest>) -> Result<Response<UpdateAgendaResponse>, Status> {
+            let message = request.into_inner();
+            let mut agendas = self.agendas.lock().unwrap();
+            let existing = agendas.iter_mut().find(|agenda| agenda.id == message.id).ok_or_else(|| Status::not_found("missing"))?;
+            *existing = Agenda {id: message.id, ..message.agenda.unwrap_or_default()};
+            Ok(Response::new(UpdateAgendaResponse {agenda: Some(existing.clone())}))
+        }
+
+        async fn delete_agenda(&self, request: Request<DeleteAgendaRequest>) -> Result<Response<DeleteAgendaResponse>, Status> {
+            let id = self.find(request.into_inner().id).ok_or_else(|| Status::not_found("missing"))?.id;
+            self.agendas.lock().unwrap().retain(|agenda| agenda.id != id);
+            Ok(Response::new(DeleteAgendaResponse {}))
+        }
+
+        async fn list_agenda_history(&self, _request: Request<ListAgendaHistoryRequest>) -> Result<Response<ListAgendaHistoryResponse>, Status> {
+            Err(Status::unimplemented("no history"))
+        }
+
+        async fn restore_agenda(&self, _request: Request<RestoreAgendaRequest>) -> Result<Response<RestoreAgendaResponse>, Status> {
+            Err(Status::unimplemented("no history"))
+        }
+
+        type ExportAgendasStream = stream::Iter<std::vec::IntoIter<Result<FileChunk, Status>>>;
+
+        // A name per line, sent in two chunks
+        async fn export_agendas(&self, _request: Request<ExportAgendasRequest>) -> Result<Response<Self::ExportAgendasStream>, Status> {
+            let names = self.agendas.lock().unwrap().iter().map(|agenda| format!("{}\n", agenda.name)).collect::<String>();
+            let (first, second) = names.as_bytes().split_at(names.len() / 2);
+            Ok(Response::new(stream::iter(vec![Ok(FileChunk {data: first.to_vec()}), Ok(FileChunk {data: second.to_vec()})])))
+        }
+
+        async fn import_agendas(&self, request: Request<tonic::Streaming<ImportAgendasRequest>>) -> Result<Response<ImportAgendasResponse>, Status> {
+            let mut messages = request.into_inner();
+            let mut data = Vec::new();
+            while let Some(message) = messages.message().await? {
+                data.extend(message.data);
+            }
+            if self.unavailable_imports.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)).is_ok() {
+                return Err(Status::unavailable("overloaded"));
+            }
+            let mut response = ImportAgendasResponse::default();
+            for (line, name) in String::from_utf8_lossy(&data).lines().enumerate() {
+                match self.create_agenda(Request::new(CreateAgendaRequest {agenda: Some(agenda(name))})).await {
+                    Ok(_) => response.imported += 1,
+                    Err(status) => response.errors.push(ImportError {record: line as i64 + 1, message: status.message().to_string()}),
+                }
+            }
+            Ok(Response::new(response))
+        }
+    }
+
+    async fn serve(service: Arc<FakeAgendaService>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(Server::builder()
+            .add_service(AgendaServiceServer::from_arc(service))
+            .serve_with_incoming(incoming));
+        endpoint
+    }
+
+    fn agenda(name: &str) -> Agenda {
+        Agenda {id: 0, name: name.to_string(), email: format!("{name}@example.com"), phone: "555".to_string()}
+    }
+
+    #[tokio::test]
+    async fn test_agenda_calls() {
+        let endpoint = serve(Arc::default()).await;
+        let client = AgendaClient::connect(ClientConfig::new(endpoint)).await.unwrap();
+
+        let created = client.create_agenda(agenda("ada")).await.unwrap();
+        assert_eq!(client.get_agenda(created.id).await.unwrap(), created);
+        assert!(matches!(client.create_agenda(agenda("ada")).await, Err(ClientError::AlreadyExists{..})));
+
+        let updated = client.update_agenda(created.id, Agenda {phone: "556".to_string(), ..agenda("ada")}).await.unwrap();
+        assert_eq!(updated.phone, "556");
+        assert_eq!(updated.id, created.id);
+
+        client.delete_agenda(created.id).await.unwrap();
+        assert!(matches!(client.get_agenda(created.id).await, Err(ClientError::NotFound{..})));
+        assert_eq!(client.restore_agenda(created.id, 1).await.unwrap_err().code(), Code::Unimplemented);
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let endpoint = serve(Arc::default()).await;
+        let client = AgendaClient::connect(ClientConfig::new(endpoint)).await.unwrap();
+
+        let response = client.import_agendas(FileFormat::Csv, b"ada\ngrace\nada\n".to_vec()).await.unwrap();
+        assert_eq!(response.imported, 2);
+        assert_eq!(response.errors, vec![ImportError {record: 3, message: "ada".to_string()}]);
+        assert_eq!(client.import_agendas(FileFormat::Csv, Vec::new()).await.unwrap().imported, 0);
+
+        assert_eq!(client.export_agendas(FileFormat::Csv).await.unwrap(), b"ada\ngrace\n");
+    }
+
+    #[tokio::test]
+    async fn test_pages() {
+        let endpoint = serve(Arc::default()).await;
+        let client = AgendaClient::connect(ClientConfig::new(endpoint)).await.unwrap();
+        for index in 0..5 {
+            client.create_agenda(agenda(&format!("agenda-{index}"))).await.unwrap();
+        }
+
+        let mut pages = client.pages(2);
+        let mut sizes = Vec::new();
+        while let Some(page) = pages.next().await {
+            sizes.push(page.unwrap().len());
+        }
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(pages.total(), Some(5));
+
+        let agendas = client.pages(3).collect().await.unwrap();
+        assert_eq!(agendas.iter().map(|agenda| agenda.id).collect::<Vec<i64>>(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let service = Arc::new(FakeAgendaService::default());
+        let endpoint = serve(service.clone()).await;
+        let retry = RetryPolicy {initial_backoff: Duration::from_millis(10), ..RetryPolicy::default()};
+        let config = ClientConfig::new(endpoint).with_api_key("agk_1_secret").with_retry(retry);
+        let client = AgendaClient::connect(config).await.unwrap();
+
+        service.unavailable_pings.store(2, Ordering::SeqCst);
+        assert_eq!(client.ping().await.unwrap(), "pong");
+        assert_eq!(service.pings.load(Ordering::SeqCst), 3);
+        assert_eq!(service.api_keys.lock().unwrap().len(), 3);
+        assert!(service.api_keys.lock().unwrap().iter().all(|api_key| api_key == "agk_1_secret"));
+
+        service.unavailable_pings.store(3, Ordering::SeqCst);
+        assert!(matches!(client.ping().await, Err(ClientError::Unavailable{..})));
+
+        service.unavailable_pings.store(1, Ordering::SeqCst);
+        assert!(matches!(client.with_retry(RetryPolicy::disabled()).ping().await, Err(ClientError::Unavailable{..})));
+
+        // Imports are not retried, even though the policy allows it
+        service.unavailable_imports.store(1, Ordering::SeqCst);
+        assert!(matches!(client.import_agendas(FileFormat::Csv, b"ada\n".to_vec()).await, Err(ClientError::Unavailable{..})));
+        assert!(service.agendas.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let endpoint = serve(Arc::default()).await;
+        let client = AgendaClient::connect(ClientConfig::new(endpoint)).await.unwrap();
+
+        // The fake service takes a second to answer when asked for pages of no items
+        let started = Instant::now();
+        let result = client.with_timeout(Duration::from_millis(100)).get_agendas(1, 0).await;
+        assert!(matches!(result, Err(ClientError::DeadlineExceeded{..})));
+        assert!(started.elapsed() < Duration::from_millis(900));
+
+        let result = client.with_deadline(Instant::now() + Duration::from_millis(100)).get_agendas(1, 0).await;
+        assert!(matches!(result, Err(ClientError::DeadlineExceeded{..})));
+        assert!(client.get_agendas(1, 1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_error() {
+        let config = ClientConfig::new("http://127.0.0.1:1").with_retry(RetryPolicy::disabled());
+        assert!(matches!(AgendaClient::connect(config.clone()).await, Err(ClientError::Connection{..})));
+
+        let client = AgendaClient::connect_lazy(config).unwrap();
+        assert!(client.ping().await.unwrap_err().is_retryable());
+    }
+}
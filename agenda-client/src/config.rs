@@ -0,0 +1,114 @@
+use std::time::Duration;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use crate::error::ClientError;
+use crate::retry::RetryPolicy;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    // PEM certificate of the authority that signed the server's certificate, the system roots when not set
+    pub ca_certificate: Option<Vec<u8>>,
+    // Name checked against the server's certificate, the endpoint's host when not set
+    pub domain: Option<String>,
+    // PEM certificate and key sent to servers which require client certificates
+    pub identity: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl TlsConfig {
+    fn client_tls_config(&self) -> ClientTlsConfig {
+        let config = match &self.ca_certificate {
+            Some(ca_certificate) => ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_certificate)),
+            None => ClientTlsConfig::new().with_native_roots(),
+        };
+        let config = match &self.domain {
+            Some(domain) => config.domain_name(domain),
+            None => config,
+        };
+        match &self.identity {
+            Some((certificate, key)) => config.identity(Identity::from_pem(certificate, key)),
+            None => config,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    // Such as "http://localhost:50051", https endpoints use the system roots unless tls is set
+    pub endpoint: String,
+    pub tls: Option<TlsConfig>,
+    // Sent as x-api-key when the server checks API keys
+    pub api_key: Option<String>,
+    // Sent as x-actor, recorded in the agenda history when calls are not made with an API key
+    pub actor: Option<String>,
+    pub connect_timeout: Duration,
+    // Deadline of each call, retries included
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+impl ClientConfig {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        ClientConfig {
+            endpoint: endpoint.into(),
+            tls: None,
+            api_key: None,
+            actor: None,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Some(Duration::from_secs(30)),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_tls(self, tls: TlsConfig) -> Self {
+        ClientConfig {tls: Some(tls), ..self}
+    }
+
+    pub fn with_api_key(self, api_key: impl Into<String>) -> Self {
+        ClientConfig {api_key: Some(api_key.into()), ..self}
+    }
+
+    pub fn with_actor(self, actor: impl Into<String>) -> Self {
+        ClientConfig {actor: Some(actor.into()), ..self}
+    }
+
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        ClientConfig {timeout, ..self}
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> Self {
+        ClientConfig {retry, ..self}
+    }
+
+    pub(crate) fn endpoint(&self) -> Result<Endpoint, ClientError> {
+        let endpoint = Endpoint::from_shared(self.endpoint.clone())
+            .map_err(|err| ClientError::Config{error: format!("invalid endpoint {}: {err}", self.endpoint)})?
+            .connect_timeout(self.connect_timeout);
+        let tls = match (&self.tls, endpoint.uri().scheme_str()) {
+            (Some(tls), _) => Some(tls.client_tls_config()),
+            (None, Some("https")) => Some(TlsConfig::default().client_tls_config()),
+            (None, _) => None,
+        };
+        match tls {
+            Some(tls) => endpoint.tls_config(tls).map_err(|err| ClientError::Config{error: format!("invalid TLS configuration: {err}")}),
+            None => Ok(endpoint),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_endpoint() {
+        let config = ClientConfig::new("http://localhost:50051").with_api_key("agk_1_secret").with_timeout(None);
+        assert_eq!(config.endpoint().unwrap().uri().port_u16(), Some(50051));
+        assert_eq!(config.api_key.as_deref(), Some("agk_1_secret"));
+        assert!(config.timeout.is_none());
+
+        assert!(matches!(ClientConfig::new("not a uri").endpoint(), Err(ClientError::Config{..})));
+        assert!(ClientConfig::new("https://agenda.example.com").endpoint().is_ok());
+    }
+}
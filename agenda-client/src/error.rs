@@ -0,0 +1,139 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+use tonic::{Code, Status};
+use tonic_types::StatusExt;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    Config{error: String},
+    Connection{error: String},
+    NotFound{message: String},
+    AlreadyExists{message: String},
+    // Field violations are (field, description) pairs of the BadRequest details
+    InvalidArgument{message: String, violations: Vec<(String, String)>},
+    Unauthenticated{message: String},
+    PermissionDenied{message: String},
+    // Rate limited, retry_after comes from the RetryInfo details
+    ResourceExhausted{message: String, retry_after: Option<Duration>},
+    Unavailable{message: String, retry_after: Option<Duration>},
+    DeadlineExceeded{message: String},
+    Status{code: Code, message: String},
+}
+
+
+impl ClientError {
+    pub fn code(&self) -> Code {
+        match self {
+            ClientError::Config{..} => Code::InvalidArgument,
+            ClientError::Connection{..} => Code::Unavailable,
+            ClientError::NotFound{..} => Code::NotFound,
+            ClientError::AlreadyExists{..} => Code::AlreadyExists,
+            ClientError::InvalidArgument{..} => Code::InvalidArgument,
+            ClientError::Unauthenticated{..} => Code::Unauthenticated,
+            ClientError::PermissionDenied{..} => Code::PermissionDenied,
+            ClientError::ResourceExhausted{..} => Code::ResourceExhausted,
+            ClientError::Unavailable{..} => Code::Unavailable,
+            ClientError::DeadlineExceeded{..} => Code::DeadlineExceeded,
+            ClientError::Status{code, ..} => *code,
+        }
+    }
+
+    // Errors that may not happen again if the same call is made later
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ClientError::Connection{..} | ClientError::Unavailable{..} | ClientError::ResourceExhausted{..})
+            || self.code() == Code::Aborted
+    }
+
+    // Delay asked by the server before calling again
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ClientError::ResourceExhausted{retry_after, ..} | ClientError::Unavailable{retry_after, ..} => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClientError::Config{error} => write!(f, "invalid configuration: {}", error),
+            ClientError::Connection{error} => write!(f, "connection error: {}", error),
+            ClientError::InvalidArgument{message, violations} if !violations.is_empty() => {
+                let violations = violations.iter().map(|(field, description)| format!("{field}: {description}")).collect::<Vec<String>>();
+                write!(f, "invalid argument: {} ({})", message, violations.join(", "))
+            }
+            ClientError::ResourceExhausted{message, retry_after: Some(retry_after)} => write!(f, "{} (retry after {:?})", message, retry_after),
+            ClientError::Status{code, message} => write!(f, "{}: {}", code.description(), message),
+            ClientError::NotFound{message} | ClientError::AlreadyExists{message} | ClientError::InvalidArgument{message, ..}
+            | ClientError::Unauthenticated{message} | ClientError::PermissionDenied{message} | ClientError::ResourceExhausted{message, ..}
+            | ClientError::Unavailable{message, ..} | ClientError::DeadlineExceeded{message} => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+
+// Decodes the status details the server attaches to its errors
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        let retry_after = status.get_details_retry_info().and_then(|info| info.retry_delay);
+        match status.code() {
+            Code::NotFound => ClientError::NotFound{message},
+            Code::AlreadyExists => ClientError::AlreadyExists{message},
+            Code::InvalidArgument => ClientError::InvalidArgument{
+                message,
+                violations: status.get_details_bad_request()
+                    .map(|bad_request| bad_request.field_violations.into_iter().map(|violation| (violation.field, violation.description)).collect())
+                    .unwrap_or_default(),
+            },
+            Code::Unauthenticated => ClientError::Unauthenticated{message},
+            Code::PermissionDenied => ClientError::PermissionDenied{message},
+            Code::ResourceExhausted => ClientError::ResourceExhausted{message, retry_after},
+            Code::Unavailable => ClientError::Unavailable{message, retry_after},
+            Code::DeadlineExceeded => ClientError::DeadlineExceeded{message},
+            code => ClientError::Status{code, message},
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for ClientError {
+    fn from(err: tonic::transport::Error) -> Self {
+        // The cause says why the connection failed, the error itself only that it did
+        let error = match err.source() {
+            Some(source) => format!("{err}: {source}"),
+            None => err.to_string(),
+        };
+        ClientError::Connection{error}
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tonic_types::{ErrorDetails, FieldViolation};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_from_status() {
+        let status = Status::with_error_details(Code::ResourceExhausted, "rate limited", ErrorDetails::with_retry_info(Some(Duration::from_secs(2))));
+        let error = ClientError::from(status);
+        assert_eq!(error, ClientError::ResourceExhausted{message: "rate limited".to_string(), retry_after: Some(Duration::from_secs(2))});
+        assert!(error.is_retryable());
+
+        let status = Status::with_error_details(Code::InvalidArgument, "invalid agenda", ErrorDetails::with_bad_request(vec![FieldViolation::new("email", "is empty")]));
+        let error = ClientError::from(status);
+        assert_eq!(error.code(), Code::InvalidArgument);
+        assert_eq!(error.to_string(), "invalid argument: invalid agenda (email: is empty)");
+        assert!(!error.is_retryable());
+
+        let error = ClientError::from(Status::new(Code::Aborted, "conflict"));
+        assert_eq!(error, ClientError::Status{code: Code::Aborted, message: "conflict".to_string()});
+        assert!(error.is_retryable());
+        assert_eq!(ClientError::from(Status::not_found("missing")).code(), Code::NotFound);
+    }
+}
@@ -0,0 +1,15 @@
+mod client;
+mod config;
+mod error;
+mod pages;
+mod retry;
+
+pub mod agenda {
+    tonic::include_proto!("agenda.v1");
+}
+
+pub use client::AgendaClient;
+pub use config::{ClientConfig, TlsConfig};
+pub use error::ClientError;
+pub use pages::AgendaPages;
+pub use retry::RetryPolicy;
@@ -0,0 +1,59 @@
+use futures_util::Stream;
+use crate::agenda::Agenda;
+use crate::client::AgendaClient;
+use crate::error::ClientError;
+
+
+// Walks GetAgendas from the first page until the server says there is no next one
+#[derive(Debug, Clone)]
+pub struct AgendaPages {
+    client: AgendaClient,
+    items: i64,
+    // 0 once the last page was returned
+    next_page: i64,
+    total: Option<i64>,
+}
+
+impl AgendaPages {
+    pub(crate) fn new(client: AgendaClient, items: i64) -> Self {
+        AgendaPages {client, items, next_page: 1, total: None}
+    }
+
+    // Number of agendas reported with the last page
+    pub fn total(&self) -> Option<i64> {
+        self.total
+    }
+
+    // Stops after an error, the same page would most likely fail again
+    pub async fn next(&mut self) -> Option<Result<Vec<Agenda>, ClientError>> {
+        if self.next_page == 0 {
+            return None;
+        }
+        let result = self.client.get_agendas(self.next_page, self.items).await;
+        match result {
+            Ok(response) => {
+                self.next_page = response.next_page;
+                self.total = Some(response.total);
+                Some(Ok(response.agendas))
+            }
+            Err(err) => {
+                self.next_page = 0;
+                Some(Err(err))
+            }
+        }
+    }
+
+    pub async fn collect(mut self) -> Result<Vec<Agenda>, ClientError> {
+        let mut agendas = Vec::new();
+        while let Some(page) = self.next().await {
+            agendas.extend(page?);
+        }
+        Ok(agendas)
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Vec<Agenda>, ClientError>> {
+        futures_util::stream::unfold(self, |mut pages| async move {
+            pages.next().await.map(|page| (page, pages))
+        })
+    }
+}
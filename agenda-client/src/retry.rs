@@ -0,0 +1,56 @@
+use std::time::Duration;
+use rand::Rng;
+
+
+// Exponential backoff between the attempts of a call the server answered with Unavailable
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // Attempts including the first one, 1 disables retries
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn disabled() -> Self {
+        RetryPolicy {max_attempts: 1, ..RetryPolicy::default()}
+    }
+
+    // Wait before the attempt following this one, jittered between half and all of the backoff so
+    // clients turned away together do not come back together
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt.saturating_sub(1) as i32);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        Duration::from_secs_f64(backoff * rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backoff() {
+        let policy = RetryPolicy::default();
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let third = policy.backoff(3);
+            assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+            assert!(policy.backoff(30) <= policy.max_backoff);
+        }
+    }
+}
@@ -1,10 +1,48 @@
+#[path = "build/openapi.rs"]
+mod openapi;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Exposed to the binary so telemetry can report the compiler that built it
+    let rustc = std::env::var("RUSTC").unwrap_or("rustc".to_string());
+    let rustc_version = std::process::Command::new(rustc)
+        .arg("--version")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).split_whitespace().nth(1).unwrap_or("unknown").to_string())
+        .unwrap_or("unknown".to_string());
+    println!("cargo:rustc-env=RUSTC_VERSION={rustc_version}");
+
+    // Printed by the version command
+    println!("cargo:rustc-env=BUILD_TARGET={}", std::env::var("TARGET")?);
+    println!("cargo:rustc-env=BUILD_PROFILE={}", std::env::var("PROFILE")?);
+    let git_commit = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or("unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={git_commit}");
+    // Commits only change files under .git, a path that does not exist would rerun this on every build
+    for path in [".git/HEAD", ".git/refs"] {
+        if std::path::Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+
+    // The descriptors let the REST gateway read the google.api.http rules and map JSON to messages
+    let descriptor_path = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("descriptor.bin");
     tonic_build::configure()
+        .file_descriptor_set_path(&descriptor_path)
         .compile(
-            &["src/proto/agenda/v1/agenda.proto"],
-            &["src/proto/agenda/v1"],
+            &["src/proto/agenda/v1/agenda.proto", "src/proto/admin/v1/admin.proto"],
+            &["src/proto/agenda/v1", "src/proto/admin/v1", "src/proto"],
         ).unwrap_or_else(
         |e| panic!("Failed to compile proto file: {:?}", e)
     );
+
+    // Served by the REST gateway, generated from the same rules so the HTTP surface matches the proto
+    let pool = prost_reflect::DescriptorPool::decode(std::fs::read(&descriptor_path)?.as_slice())?;
+    let document = openapi::generate(&pool, "agenda.v1.AgendaService", &std::env::var("CARGO_PKG_VERSION")?)?;
+    std::fs::write(descriptor_path.with_file_name("openapi.json"), serde_json::to_string_pretty(&document)?)?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use prost_reflect::{DescriptorPool, FieldDescriptor, Kind, MessageDescriptor, MethodDescriptor};
+use prost_types::FileDescriptorProto;
+use serde_json::{json, Map, Value};
+
+// The HTTP statuses returned by the gateway with the gRPC codes mapped to each, keep in sync with gateway::http_status
+const ERRORS: [(&str, &str); 11] = [
+    ("400", "INVALID_ARGUMENT, FAILED_PRECONDITION, OUT_OF_RANGE"),
+    ("401", "UNAUTHENTICATED"),
+    ("403", "PERMISSION_DENIED"),
+    ("404", "NOT_FOUND"),
+    ("409", "ALREADY_EXISTS, ABORTED"),
+    ("429", "RESOURCE_EXHAUSTED"),
+    ("499", "CANCELLED"),
+    ("500", "UNKNOWN, INTERNAL, DATA_LOSS"),
+    ("501", "UNIMPLEMENTED"),
+    ("503", "UNAVAILABLE"),
+    ("504", "DEADLINE_EXCEEDED"),
+];
+
+const PATTERNS: [&str; 5] = ["get", "put", "post", "delete", "patch"];
+
+
+struct Rule {
+    method: &'static str,
+    path: String,
+    body: Option<String>,
+}
+
+fn http_rule(pool: &DescriptorPool, rpc: &MethodDescriptor) -> Option<Rule> {
+    let extension = pool.get_extension_by_name("google.api.http")?;
+    let options = rpc.options();
+    if !options.has_extension(&extension) {
+        return None;
+    }
+    let rule = options.get_extension(&extension).as_message()?.clone();
+    let (method, path) = PATTERNS.iter().find_map(|method| {
+        rule.has_field_by_name(method)
+            .then(|| rule.get_field_by_name(method))
+            .flatten()
+            .and_then(|path| path.as_str().map(|path| (*method, path.to_string())))
+    })?;
+    let body = rule.get_field_by_name("body")
+        .and_then(|body| body.as_str().map(|body| body.to_string()))
+        .filter(|body| !body.is_empty());
+    Some(Rule {method, path, body})
+}
+
+// Path parameters are written "{id}" or "{id=pattern}" in the rule, OpenAPI only accepts "{id}"
+fn path_parameters(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')))
+        .map(|field| field.split('=').next().unwrap_or(field).to_string())
+        .collect()
+}
+
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) {
+            Some(field) => format!("{{{}}}", field.split('=').next().unwrap_or(field)),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+// Leading comment of the element at this path of its file, requires the source info kept by protoc
+fn comments(file: &FileDescriptorProto, path: &[i32]) -> Option<String> {
+    file.source_code_info.as_ref()?
+        .location.iter()
+        .find(|location| location.path == path)
+        .and_then(|location| location.leading_comments.as_ref())
+        .map(|comments| comments.lines().map(|line| line.trim()).collect::<Vec<&str>>().join(" ").trim().to_string())
+        .filter(|comments| !comments.is_empty())
+}
+
+
+fn schema_ref(message: &MessageDescriptor) -> Value {
+    json!({"$ref": format!("#/components/schemas/{}", message.full_name())})
+}
+
+// Schemas follow the proto3 JSON mapping, 64 bits integers are strings and well-known types their JSON form
+fn kind_schema(kind: &Kind, messages: &mut Vec<MessageDescriptor>) -> Value {
+    match kind {
+        Kind::Double => json!({"type": "number", "format": "double"}),
+        Kind::Float => json!({"type": "number", "format": "float"}),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => json!({"type": "integer", "format": "int32"}),
+        Kind::Uint32 | Kind::Fixed32 => json!({"type": "integer", "format": "int64", "minimum": 0}),
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => json!({"type": "string", "format": "int64"}),
+        Kind::Uint64 | Kind::Fixed64 => json!({"type": "string", "format": "uint64"}),
+        Kind::Bool => json!({"type": "boolean"}),
+        Kind::String => json!({"type": "string"}),
+        Kind::Bytes => json!({"type": "string", "format": "byte"}),
+        Kind::Enum(enumeration) => json!({"type": "string", "enum": enumeration.values().map(|value| value.name().to_string()).collect::<Vec<String>>()}),
+        Kind::Message(message) => match message.full_name() {
+            "google.protobuf.Timestamp" => json!({"type": "string", "format": "date-time"}),
+            "google.protobuf.Duration" => json!({"type": "string", "example": "1.5s"}),
+            "google.protobuf.Empty" => json!({"type": "object"}),
+            "google.protobuf.FieldMask" => json!({"type": "string", "example": "name,email"}),
+            _ => {
+                messages.push(message.clone());
+                schema_ref(message)
+            }
+        },
+    }
+}
+
+fn field_schema(field: &FieldDescriptor, messages: &mut Vec<MessageDescriptor>) -> Value {
+    let mut schema = match field.kind() {
+        Kind::Message(entry) if field.is_map() => json!({
+            "type": "object",
+            "additionalProperties": kind_schema(&entry.map_entry_value_field().kind(), messages),
+        }),
+        kind if field.is_list() => json!({"type": "array", "items": kind_schema(&kind, messages)}),
+        kind => kind_schema(&kind, messages),
+    };
+    if let Some(description) = comments(field.parent_file().file_descriptor_proto(), field.path()) {
+        // Siblings of a $ref are ignored by OpenAPI 3.0, so the reference is wrapped
+        schema = match schema.get("$ref") {
+            Some(_) => json!({"allOf": [schema], "description": description}),
+            None => {
+                schema["description"] = Value::String(description);
+                schema
+            }
+        };
+    }
+    schema
+}
+
+fn message_schema(message: &MessageDescriptor, messages: &mut Vec<MessageDescriptor>) -> Value {
+    let properties = message.fields()
+        .map(|field| (field.json_name().to_string(), field_schema(&field, messages)))
+        .collect::<Map<String, Value>>();
+    let mut schema = json!({"type": "object", "properties": properties});
+    if let Some(description) = comments(message.parent_file().file_descriptor_proto(), message.path()) {
+        schema["description"] = Value::String(description);
+    }
+    schema
+}
+
+
+// Fields which are neither in the path nor in the body are read from the query string, only the
+// scalar ones since nested messages cannot be given there
+fn parameters(rpc: &MethodDescriptor, rule: &Rule, messages: &mut Vec<MessageDescriptor>) -> Vec<Value> {
+    let in_path = path_parameters(&rule.path);
+    rpc.input().fields()
+        .filter(|field| rule.body.as_deref() != Some("*") && rule.body.as_deref() != Some(field.name()))
+        .filter_map(|field| {
+            let required = in_path.iter().any(|name| name == field.name());
+            let schema = field_schema(&field, messages);
+            let scalar = schema.get("$ref").is_none() && schema.get("allOf").is_none() && !field.is_map();
+            (required || scalar).then(|| json!({
+                "name": if required { field.name().to_string() } else { field.json_name().to_string() },
+                "in": if required { "path" } else { "query" },
+                "required": required,
+                "schema": schema,
+            }))
+        })
+        .collect()
+}
+
+fn operation(rpc: &MethodDescriptor, rule: &Rule, messages: &mut Vec<MessageDescriptor>) -> Value {
+    let mut responses = Map::from_iter([(
+        "200".to_string(),
+        json!({"description": "OK", "content": {"application/json": {"schema": schema_ref(&rpc.output())}}}),
+    )]);
+    messages.push(rpc.output());
+    for (status, _) in ERRORS {
+        responses.insert(status.to_string(), json!({"$ref": format!("#/components/responses/{status}")}));
+    }
+
+    let mut operation = json!({
+        "operationId": rpc.name(),
+        "tags": [rpc.parent_service().name()],
+        "parameters": parameters(rpc, rule, messages),
+        "responses": responses,
+    });
+    if let Some(description) = comments(rpc.parent_file().file_descriptor_proto(), rpc.path()) {
+        operation["summary"] = Value::String(description);
+    }
+    let body_schema = match rule.body.as_deref() {
+        Some("*") => {
+            messages.push(rpc.input());
+            Some(schema_ref(&rpc.input()))
+        }
+        Some(field) => rpc.input().get_field_by_name(field).map(|field| field_schema(&field, messages)),
+        None => None,
+    };
+    if let Some(schema) = body_schema {
+        operation["requestBody"] = json!({"required": true, "content": {"application/json": {"schema": schema}}});
+    }
+    operation
+}
+
+
+// OpenAPI 3 document of the RPCs of the service which have a google.api.http rule
+pub fn generate(pool: &DescriptorPool, service: &str, version: &str) -> Result<Value, String> {
+    let service = pool.get_service_by_name(service).ok_or(format!("unknown service {service}"))?;
+    let mut messages = Vec::new();
+    let mut paths = BTreeMap::<String, Map<String, Value>>::new();
+    for rpc in service.methods() {
+        let Some(rule) = http_rule(pool, &rpc) else {
+            continue;
+        };
+        let operation = operation(&rpc, &rule, &mut messages);
+        paths.entry(openapi_path(&rule.path)).or_default().insert(rule.method.to_string(), operation);
+    }
+
+    let mut schemas = BTreeMap::new();
+    while let Some(message) = messages.pop() {
+        if !schemas.contains_key(message.full_name()) {
+            let schema = message_schema(&message, &mut messages);
+            schemas.insert(message.full_name().to_string(), schema);
+        }
+    }
+    schemas.insert("google.rpc.Status".to_string(), json!({
+        "type": "object",
+        "properties": {
+            "code": {"type": "integer", "format": "int32", "description": "gRPC status code"},
+            "message": {"type": "string"},
+        },
+    }));
+
+    let responses = ERRORS.iter()
+        .map(|(status, codes)| (status.to_string(), json!({
+            "description": format!("gRPC {codes}"),
+            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/google.rpc.Status"}}},
+        })))
+        .collect::<Map<String, Value>>();
+
+    let mut info = json!({"title": format!("{} API", service.name()), "version": version});
+    if let Some(description) = comments(service.parent_file().file_descriptor_proto(), service.path()) {
+        info["description"] = Value::String(description);
+    }
+
+    Ok(json!({
+        "openapi": "3.0.3",
+        "info": info,
+        "tags": [{"name": service.name()}],
+        "paths": paths,
+        // Keys are only checked when API_KEY_AUTH is enabled
+        "security": [{}, {"ApiKey": []}],
+        "components": {
+            "schemas": schemas,
+            "responses": responses,
+            "securitySchemes": {"ApiKey": {"type": "apiKey", "in": "header", "name": "x-api-key"}},
+        },
+    }))
+}
@@ -0,0 +1,69 @@
+use tonic::Request;
+use crate::auth::Principal;
+
+// Metadata key that callers use to identify who is performing a mutation
+pub const ACTOR_METADATA_KEY: &str = "x-actor";
+
+
+// Returns the actor of a request: the API key that authenticated it, otherwise the peer address.
+// Anyone can send the actor metadata, so without a key it is only recorded as what the caller claims.
+pub fn actor_from_request<T>(request: &Request<T>) -> String {
+    if let Some(principal) = request.extensions().get::<Principal>() {
+        return principal.actor();
+    }
+
+    let peer = match request.remote_addr() {
+        Some(addr) => format!("peer:{addr}"),
+        None => "unknown".to_string(),
+    };
+    let claimed = request.metadata()
+        .get(ACTOR_METADATA_KEY)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty());
+
+    match claimed {
+        Some(claimed) => format!("{peer} (claims {claimed})"),
+        None => peer,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tonic::transport::server::TcpConnectInfo;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_actor_from_metadata() {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(ACTOR_METADATA_KEY, "alice".parse().unwrap());
+
+        assert_eq!(actor_from_request(&request), "unknown (claims alice)");
+    }
+
+    #[tokio::test]
+    async fn test_actor_from_peer() {
+        let mut request = Request::new(());
+        request.extensions_mut().insert(TcpConnectInfo {local_addr: None, remote_addr: Some("10.0.0.1:4000".parse().unwrap())});
+        assert_eq!(actor_from_request(&request), "peer:10.0.0.1:4000");
+
+        request.metadata_mut().insert(ACTOR_METADATA_KEY, "alice".parse().unwrap());
+        assert_eq!(actor_from_request(&request), "peer:10.0.0.1:4000 (claims alice)");
+    }
+
+    #[tokio::test]
+    async fn test_actor_from_principal() {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(ACTOR_METADATA_KEY, "alice".parse().unwrap());
+        request.extensions_mut().insert(Principal {key_id: 7, name: "ci".into(), scopes: vec![]});
+
+        assert_eq!(actor_from_request(&request), "key:7");
+    }
+
+    #[tokio::test]
+    async fn test_actor_unknown() {
+        let request = Request::new(());
+
+        assert_eq!(actor_from_request(&request), "unknown");
+    }
+}
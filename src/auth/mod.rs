@@ -0,0 +1,120 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+use argon2::password_hash::rand_core::OsRng;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::RngCore;
+
+// Keys look like "agk_<id>_<secret>", the id is public and selects the stored hash
+const API_KEY_PREFIX: &str = "agk_";
+
+pub const SCOPE_READ: &str = "agendas:read";
+pub const SCOPE_WRITE: &str = "agendas:write";
+// Needed for every method of the admin service, which can create keys of any scope
+pub const SCOPE_ADMIN: &str = "admin";
+pub const SCOPES: [&str; 3] = [SCOPE_READ, SCOPE_WRITE, SCOPE_ADMIN];
+
+const ADMIN_SERVICE: &str = "admin.v1.AdminService";
+
+// Agenda methods that only read, every other method needs the write scope
+const READ_METHODS: [&str; 5] = ["Ping", "GetAgenda", "GetAgendas", "ListAgendaHistory", "ExportAgendas"];
+
+
+// The caller authenticated by an API key, added to the request extensions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub key_id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    pub fn actor(&self) -> String {
+        format!("key:{}", self.key_id)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|granted| granted == scope)
+    }
+}
+
+
+pub fn required_scope(service: &str, method: &str) -> &'static str {
+    if service == ADMIN_SERVICE {
+        SCOPE_ADMIN
+    } else if READ_METHODS.contains(&method) {
+        SCOPE_READ
+    } else {
+        SCOPE_WRITE
+    }
+}
+
+
+// A random secret and its argon2 hash, the secret is only handed to the caller once
+pub fn generate_secret() -> Result<(String, String), argon2::password_hash::Error> {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let secret = URL_SAFE_NO_PAD.encode(bytes);
+    let hash = hash_secret(&secret)?;
+    Ok((secret, hash))
+}
+
+pub fn hash_secret(secret: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(secret.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_secret(secret: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(secret.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+
+pub fn format_api_key(id: i64, secret: &str) -> String {
+    format!("{API_KEY_PREFIX}{id}_{secret}")
+}
+
+// Splits a key into its id and secret, the secret may itself contain underscores
+pub fn parse_api_key(key: &str) -> Option<(i64, &str)> {
+    let (id, secret) = key.strip_prefix(API_KEY_PREFIX)?.split_once('_')?;
+    let id = id.parse::<i64>().ok()?;
+    (!secret.is_empty()).then_some((id, secret))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_api_key() {
+        assert_eq!(parse_api_key(&format_api_key(42, "se_cr-et")), Some((42, "se_cr-et")));
+        assert_eq!(parse_api_key("agk_42_"), None);
+        assert_eq!(parse_api_key("agk_x_secret"), None);
+        assert_eq!(parse_api_key("42_secret"), None);
+    }
+
+    #[tokio::test]
+    async fn test_generate_and_verify_secret() {
+        let (secret, hash) = generate_secret().unwrap();
+
+        assert!(!hash.contains(&secret));
+        assert!(verify_secret(&secret, &hash));
+        assert!(!verify_secret("wrong", &hash));
+        assert!(!verify_secret(&secret, "not a hash"));
+    }
+
+    #[tokio::test]
+    async fn test_required_scope() {
+        let principal = Principal {key_id: 1, name: "ci".into(), scopes: vec![SCOPE_READ.into()]};
+
+        let agenda = "agenda.v1.AgendaService";
+
+        assert!(principal.has_scope(required_scope(agenda, "GetAgenda")));
+        assert!(!principal.has_scope(required_scope(agenda, "DeleteAgenda")));
+        assert!(principal.has_scope(required_scope(agenda, "ExportAgendas")));
+        assert!(!principal.has_scope(required_scope(agenda, "ImportAgendas")));
+        assert_eq!(required_scope(ADMIN_SERVICE, "ListApiKeys"), SCOPE_ADMIN);
+    }
+}
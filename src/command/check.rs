@@ -0,0 +1,109 @@
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+use http::Uri;
+use crate::database::database_object::DBLayers;
+use crate::middleware::auth::{ApiKeyAuth, ApiKeyAuthLayer};
+use crate::middleware::cors::cors_layer_from_env;
+use crate::middleware::limits::RequestLimits;
+use crate::middleware::ratelimit::RateLimits;
+use crate::otel::exporter_endpoints;
+
+// Telemetry endpoints that do not accept a connection by then are reported unreachable
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+
+// Exporters only connect when they send, so reaching the endpoint is all that can be checked up front
+async fn check_endpoint(endpoint: &str) -> Result<String, String> {
+    let uri = endpoint.parse::<Uri>().map_err(|err| format!("invalid endpoint {endpoint}: {err}"))?;
+    let host = uri.host().ok_or(format!("invalid endpoint {endpoint}: no host"))?;
+    let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("https") { 443 } else { 80 });
+    // Brackets are part of IPv6 hosts in URIs, not of the address
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    match tokio::time::timeout(CONNECT_TIMEOUT, tokio::net::TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Ok(format!("{endpoint} is reachable")),
+        Ok(Err(err)) => Err(format!("{endpoint} is unreachable: {err}")),
+        Err(_) => Err(format!("{endpoint} is unreachable: no answer after {CONNECT_TIMEOUT:?}")),
+    }
+}
+
+async fn check_database() -> Result<String, String> {
+    let database = DBLayers::new_db_handler().await.map_err(|err| err.to_string())?;
+    ApiKeyAuthLayer::from_env(Arc::new(database.clone()))?;
+
+    let migrations = database.get_db_handler().retrieve_migrations().await;
+    database.get_db_handler().close().await;
+    let migrations = migrations.map_err(|err| err.to_string())?;
+
+    // Pending migrations are applied when serving starts, they are not a failure
+    let pending = migrations.iter().filter(|migration| !migration.is_applied()).count();
+    Ok(format!("connected, {} migrations applied, {pending} pending", migrations.len() - pending))
+}
+
+// Runs every check, even after one fails, and reports them all
+pub async fn check_config() -> Result<(), Box<dyn Error>> {
+    let mut checks: Vec<(String, Result<String, String>)> = vec![
+        ("addresses".to_string(), crate::server_addrs().map(|(addr, admin_addr, gateway_addr)| {
+            format!("agenda {addr}, admin {admin_addr}, gateway {gateway_addr}")
+        })),
+        ("request limits".to_string(), RequestLimits::from_env().map(|_| "valid".to_string())),
+        ("rate limits".to_string(), RateLimits::from_env().map(|_| "valid".to_string())),
+        ("peer rate limits".to_string(), RateLimits::peer_from_env().map(|_| "valid".to_string())),
+        ("cors".to_string(), cors_layer_from_env().map(|_| "valid".to_string())),
+        ("api keys".to_string(), ApiKeyAuth::from_env().map(|mode| format!("{mode:?}").to_lowercase())),
+        ("database".to_string(), check_database().await),
+    ];
+    for (signal, endpoint) in exporter_endpoints() {
+        checks.push((format!("{signal} exporter"), check_endpoint(&endpoint).await));
+    }
+
+    let mut failed = 0;
+    for (name, result) in checks {
+        match result {
+            Ok(detail) => println!("ok    {name}: {detail}"),
+            Err(error) => {
+                println!("FAIL  {name}: {error}");
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        failed => Err(Box::<dyn Error>::from(format!("{failed} checks failed"))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_endpoint() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        assert!(check_endpoint(&endpoint).await.is_ok());
+        drop(listener);
+
+        assert!(check_endpoint(&endpoint).await.is_err());
+        assert!(check_endpoint("not a uri").await.is_err());
+        assert!(check_endpoint("/no/host").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_config() {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        assert!(check_config().await.is_ok());
+
+        std::env::set_var("API_KEY_AUTH", "sometimes");
+        assert!(check_config().await.is_err());
+        std::env::remove_var("API_KEY_AUTH");
+
+        std::env::set_var("OTEL_EXPORTER_TRACES", "otlp");
+        std::env::set_var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT", "http://127.0.0.1:1");
+        assert!(check_config().await.is_err());
+        std::env::remove_var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT");
+        std::env::remove_var("OTEL_EXPORTER_TRACES");
+    }
+}
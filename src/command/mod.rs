@@ -0,0 +1,136 @@
+mod check;
+mod transfer;
+
+use std::error::Error;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use crate::auth::{format_api_key, generate_secret, SCOPES};
+use crate::database::database_object::DBLayers;
+use crate::model::{micros_to_timestamp, ApiKeyModel};
+
+pub use crate::command::check::check_config;
+pub use crate::command::transfer::{export_agendas, import_agendas};
+
+
+// Every command runs from the same binary, the container image has no shell or other tools
+#[derive(Debug, Parser)]
+#[command(name = "tonic-server", version, about = "Agenda gRPC server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Serves the agenda and admin services and the REST gateway, the default")]
+    Serve,
+    #[command(about = "Applies the pending schema migrations")]
+    Migrate {
+        #[arg(long, help = "Lists the migrations and whether they are applied, without applying any")]
+        status: bool,
+    },
+    #[command(about = "Validates the configuration and checks the database and telemetry endpoints are reachable")]
+    CheckConfig,
+    #[command(about = "Writes every agenda to a JSON file, - for the standard output")]
+    Export {
+        file: PathBuf,
+        #[arg(long, default_value_t = 1000)]
+        page_size: i64,
+    },
+    #[command(about = "Creates or replaces the agendas of a JSON file written by export, keeping their ids")]
+    Import {
+        file: PathBuf,
+    },
+    #[command(about = "Creates an API key and prints it, the first admin key has to be created this way")]
+    CreateApiKey {
+        name: String,
+        #[arg(long = "scope", required = true, help = "agendas:read, agendas:write or admin, repeated for several scopes")]
+        scopes: Vec<String>,
+    },
+    #[command(about = "Prints the version and build information")]
+    Version,
+}
+
+
+pub fn version() -> String {
+    format!(
+        "tonic-server {}\ncommit {}\nrustc {}\ntarget {}\nprofile {}",
+        env!("CARGO_PKG_VERSION"), env!("GIT_COMMIT"), env!("RUSTC_VERSION"), env!("BUILD_TARGET"), env!("BUILD_PROFILE"),
+    )
+}
+
+pub async fn migrate(status: bool) -> Result<(), Box<dyn Error>> {
+    let database = DBLayers::new_db_handler().await?;
+    let database = database.get_db_handler();
+
+    let migrations = match status {
+        true => database.retrieve_migrations().await,
+        false => database.migrate().await,
+    };
+    let migrations = migrations.map_err(|err| err.to_string())?;
+    if !status && migrations.is_empty() {
+        println!("The schema is up to date");
+    }
+    for migration in migrations {
+        let applied = match migration.applied_at {
+            Some(applied_at) => format!("applied {}", micros_to_timestamp(applied_at)),
+            None => "pending".to_string(),
+        };
+        println!("{:>4}  {:<24}  {}", migration.version, migration.name, applied);
+    }
+    database.close().await;
+    Ok(())
+}
+
+// The key is printed alone on the standard output so it can be captured by a script
+pub async fn create_api_key(name: String, scopes: Vec<String>) -> Result<(), Box<dyn Error>> {
+    if let Some(scope) = scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+        return Err(Box::<dyn Error>::from(format!("unknown scope {scope}, expected one of: {}", SCOPES.join(", "))));
+    }
+    let (secret, secret_hash) = generate_secret().map_err(|err| err.to_string())?;
+    let database = DBLayers::new_db_handler().await?;
+    let result = database.get_db_handler()
+        .create_api_key(ApiKeyModel::new(name, secret_hash, scopes, None))
+        .await;
+    database.get_db_handler().close().await;
+
+    let api_key = result.map_err(|err| err.to_string())?;
+    eprintln!("Created API key {} ({}), it is only shown once", api_key.id, api_key.name);
+    println!("{}", format_api_key(api_key.id, &secret));
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cli() {
+        Cli::command().debug_assert();
+
+        assert!(Cli::try_parse_from(["tonic-server"]).unwrap().command.is_none());
+        assert!(matches!(Cli::try_parse_from(["tonic-server", "migrate", "--status"]).unwrap().command, Some(Command::Migrate {status: true})));
+        assert!(matches!(Cli::try_parse_from(["tonic-server", "export", "-"]).unwrap().command, Some(Command::Export {page_size: 1000, ..})));
+        assert!(Cli::try_parse_from(["tonic-server", "import"]).is_err());
+        assert!(matches!(
+            Cli::try_parse_from(["tonic-server", "create-api-key", "ops", "--scope", "admin"]).unwrap().command,
+            Some(Command::CreateApiKey {scopes, ..}) if scopes == ["admin"]
+        ));
+        assert!(Cli::try_parse_from(["tonic-server", "create-api-key", "ops"]).is_err());
+        assert!(version().starts_with(&format!("tonic-server {}", env!("CARGO_PKG_VERSION"))));
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        assert!(migrate(false).await.is_ok());
+
+        let database = DBLayers::new_db_handler().await.unwrap();
+        let migrations = database.get_db_handler().retrieve_migrations().await.unwrap();
+        assert!(migrations.iter().all(|migration| migration.is_applied()));
+        assert!(database.get_db_handler().migrate().await.unwrap().is_empty());
+        assert!(migrate(true).await.is_ok());
+    }
+}
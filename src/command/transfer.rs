@@ -0,0 +1,168 @@
+use std::error::Error;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use serde_json::{json, Value};
+use crate::database::Database;
+use crate::database::database_object::DBLayers;
+use crate::model::AgendaModel;
+
+// The history records imported agendas as changed by the import command
+const IMPORT_ACTOR: &str = "import";
+
+
+fn agenda_json(agenda: &AgendaModel) -> Value {
+    json!({"id": agenda.id, "name": agenda.name, "email": agenda.email, "phone": agenda.phone})
+}
+
+fn agenda_from_json(value: &Value) -> Result<AgendaModel, String> {
+    let field = |name: &str| value.get(name).and_then(|field| field.as_str()).map(|field| field.to_string())
+        .ok_or(format!("missing {name}"));
+    Ok(AgendaModel {
+        id: value.get("id").and_then(|id| id.as_i64()).filter(|id| *id > 0).ok_or("missing id")?,
+        name: field("name")?,
+        email: field("email")?,
+        phone: field("phone")?,
+    })
+}
+
+
+// Writes a JSON array a page at a time, so the table does not have to fit in memory
+pub async fn write_agendas(database: &dyn Database, mut writer: impl Write, page_size: i64) -> Result<usize, Box<dyn Error>> {
+    if page_size < 1 {
+        return Err(Box::<dyn Error>::from("the page size must be at least 1"));
+    }
+    let mut written = 0;
+    let mut after_id = 0;
+    writer.write_all(b"[")?;
+    loop {
+        let agendas = database.retrieve_after(after_id, page_size).await.map_err(|err| err.to_string())?;
+        for agenda in &agendas {
+            writer.write_all(if written == 0 { b"\n  " } else { b",\n  " })?;
+            serde_json::to_writer(&mut writer, &agenda_json(agenda))?;
+            written += 1;
+        }
+        match agendas.last() {
+            Some(last) if agendas.len() as i64 == page_size => after_id = last.id,
+            _ => break,
+        }
+    }
+    writer.write_all(b"\n]\n")?;
+    writer.flush()?;
+    Ok(written)
+}
+
+// Agendas keep their ids, existing ones are replaced. Every record is tried, the failed ones are
+// returned with their position in the file.
+pub async fn read_agendas(database: &dyn Database, reader: impl Read) -> Result<(usize, Vec<String>), Box<dyn Error>> {
+    let values: Vec<Value> = serde_json::from_reader(reader).map_err(|err| format!("invalid JSON: {err}"))?;
+    let mut imported = 0;
+    let mut failures = Vec::new();
+    for (index, value) in values.iter().enumerate() {
+        let result = match agenda_from_json(value) {
+            Ok(agenda) => database.upsert_agenda(agenda, IMPORT_ACTOR, "Import").await.map_err(|err| err.to_string()),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(_) => imported += 1,
+            Err(err) => failures.push(format!("record {}: {err}", index + 1)),
+        }
+    }
+    Ok((imported, failures))
+}
+
+
+// "-" is the standard output, so the export can be redirected from a container without a volume
+pub async fn export_agendas(file: &Path, page_size: i64) -> Result<(), Box<dyn Error>> {
+    let database = DBLayers::new_db_handler().await?;
+    let written = match file.to_str() {
+        Some("-") => write_agendas(database.get_db_handler(), std::io::stdout().lock(), page_size).await?,
+        _ => {
+            let writer = std::fs::File::create(file).map_err(|err| format!("{}: {err}", file.display()))?;
+            write_agendas(database.get_db_handler(), BufWriter::new(writer), page_size).await?
+        }
+    };
+    database.get_db_handler().close().await;
+    eprintln!("{written} agendas exported");
+    Ok(())
+}
+
+pub async fn import_agendas(file: &Path) -> Result<(), Box<dyn Error>> {
+    let database = DBLayers::new_db_handler().await?;
+    let result = match file.to_str() {
+        Some("-") => read_agendas(database.get_db_handler(), std::io::stdin().lock()).await,
+        _ => {
+            let reader = std::fs::File::open(file).map_err(|err| format!("{}: {err}", file.display()))?;
+            read_agendas(database.get_db_handler(), BufReader::new(reader)).await
+        }
+    };
+    database.get_db_handler().close().await;
+
+    let (imported, failures) = result?;
+    for failure in &failures {
+        eprintln!("{failure}");
+    }
+    eprintln!("{imported} agendas imported");
+    match failures.len() {
+        0 => Ok(()),
+        failed => Err(Box::<dyn Error>::from(format!("{failed} records could not be imported"))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_agenda_from_json() {
+        let agenda = agenda_from_json(&json!({"id": 3, "name": "n", "email": "e", "phone": "p"})).unwrap();
+        assert_eq!(agenda, AgendaModel {id: 3, name: "n".into(), email: "e".into(), phone: "p".into()});
+        assert_eq!(agenda_from_json(&json!({"name": "n", "email": "e", "phone": "p"})).unwrap_err(), "missing id");
+        assert_eq!(agenda_from_json(&json!({"id": 3, "name": "n", "email": "e"})).unwrap_err(), "missing phone");
+    }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let database = DBLayers::new_db_handler().await.unwrap();
+        let database = database.get_db_handler();
+        database.init_database().await.unwrap();
+        let name = format!("transfer-{}", uuid::Uuid::new_v4());
+        let created = database.create_agenda(AgendaModel {id: 0, name: name.clone(), email: "a@b.c".into(), phone: "1".into()}, "actor", "CreateAgenda").await.unwrap();
+
+        let mut exported = Vec::new();
+        let written = write_agendas(database, &mut exported, 2).await.unwrap();
+        let values: Vec<Value> = serde_json::from_slice(&exported).unwrap();
+        assert_eq!(values.len(), written);
+        assert!(values.iter().any(|value| value["name"] == name.as_str() && value["id"] == created.id));
+
+        // An id past the sequence is imported as is, and the agendas created afterwards come after it
+        let imported_id = created.id + 1000;
+        let file = json!([
+            {"id": created.id, "name": name, "email": "new@b.c", "phone": "2"},
+            {"id": imported_id, "name": format!("{name}-imported"), "email": "i@b.c", "phone": "3"},
+            {"id": imported_id + 1, "name": name, "email": "duplicate@b.c", "phone": "4"},
+            {"name": "no id"},
+        ]);
+        let (imported, failures) = read_agendas(database, file.to_string().as_bytes()).await.unwrap();
+        assert_eq!(imported, 2);
+        assert_eq!(failures.len(), 2);
+        assert!(failures[0].starts_with("record 3: "));
+        assert_eq!(failures[1], "record 4: missing id");
+
+        assert_eq!(database.retrieve_from_id(created.id).await.unwrap().email, "new@b.c");
+        assert_eq!(database.retrieve_from_id(imported_id).await.unwrap().phone, "3");
+
+        // The import is in the history of the agendas it replaced or created
+        let (revisions, _, _) = database.retrieve_agenda_history(created.id, 1, 10).await.unwrap();
+        let revision = revisions.last().unwrap();
+        assert_eq!((revision.actor.as_str(), revision.method.as_str()), (IMPORT_ACTOR, "Import"));
+        assert_eq!(revision.before, Some(created.clone()));
+        assert_eq!(revision.after.as_ref().map(|agenda| agenda.email.as_str()), Some("new@b.c"));
+        let (revisions, _, _) = database.retrieve_agenda_history(imported_id, 1, 10).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].before, None);
+        let next = database.create_agenda(AgendaModel {id: 0, name: format!("{name}-next"), email: "n@b.c".into(), phone: "5".into()}, "actor", "CreateAgenda").await.unwrap();
+        assert!(next.id > imported_id);
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tonic::async_trait;
+use crate::database::Database;
+use crate::database::database_object::DBLayers;
+use crate::database::error::DatabaseError;
+use crate::database::routing::read_from_replica;
+use crate::model::{AgendaModel, ApiKeyModel, AuditModel, MigrationModel};
+use crate::otel::METRICS;
+
+
+#[derive(Debug)]
+struct Entry {
+    agenda: AgendaModel,
+    expires_at: Instant,
+    last_used: u64,
+}
+
+// Least recently used agendas are evicted first, `recency` orders the ids by their last use
+#[derive(Debug)]
+struct LruCache {
+    capacity: usize,
+    ttl: Duration,
+    tick: u64,
+    // Bumped on every invalidation, a read that started before it must not fill the cache
+    generation: u64,
+    entries: HashMap<i64, Entry>,
+    recency: BTreeMap<u64, i64>,
+}
+
+impl LruCache {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        LruCache {
+            capacity,
+            ttl,
+            tick: 0,
+            generation: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, id: i64) -> Option<AgendaModel> {
+        let expired = self.entries.get(&id)?.expires_at <= Instant::now();
+        if expired {
+            self.remove(id);
+            return None;
+        }
+
+        self.tick += 1;
+        let entry = self.entries.get_mut(&id)?;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.tick;
+        self.recency.insert(self.tick, id);
+        Some(entry.agenda.clone())
+    }
+
+    fn insert(&mut self, agenda: AgendaModel, generation: u64) {
+        if self.capacity == 0 || generation != self.generation {
+            return;
+        }
+        self.remove(agenda.id);
+        while self.entries.len() >= self.capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => self.entries.remove(&oldest),
+                None => break,
+            };
+        }
+
+        self.tick += 1;
+        self.recency.insert(self.tick, agenda.id);
+        self.entries.insert(agenda.id, Entry {
+            expires_at: Instant::now() + self.ttl,
+            last_used: self.tick,
+            agenda,
+        });
+    }
+
+    fn remove(&mut self, id: i64) {
+        if let Some(entry) = self.entries.remove(&id) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+
+    fn invalidate(&mut self, id: i64) {
+        self.generation += 1;
+        self.remove(id);
+    }
+}
+
+
+// Caches agendas read by id in front of any database layer. Only reads that may be served by a
+// replica are cached, so writes and read-your-writes requests always see the database.
+// Writes through this process invalidate the cache, writes from other instances show after the ttl.
+#[derive(Debug)]
+pub struct CachedDatabase {
+    inner: DBLayers,
+    cache: Mutex<LruCache>,
+}
+
+impl CachedDatabase {
+    pub fn new(inner: DBLayers, capacity: usize, ttl: Duration) -> Self {
+        CachedDatabase {
+            inner,
+            cache: Mutex::new(LruCache::new(capacity, ttl)),
+        }
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, LruCache> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn invalidation(&self, id: i64) -> Invalidation<'_> {
+        Invalidation {cache: &self.cache, id}
+    }
+}
+
+
+// Invalidates an agenda when the write is done, failed or cancelled, as it may have been applied in
+// every case. Bumping the generation drops the reads which started before and are still running.
+struct Invalidation<'a> {
+    cache: &'a Mutex<LruCache>,
+    id: i64,
+}
+
+impl Drop for Invalidation<'_> {
+    fn drop(&mut self) {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).invalidate(self.id);
+    }
+}
+
+
+#[async_trait]
+impl Database for CachedDatabase {
+    async fn init_database(&self) -> Result<(), Box<dyn Error>> {
+        self.inner.get_db_handler().init_database().await
+    }
+
+    async fn retrieve_migrations(&self) -> Result<Vec<MigrationModel>, DatabaseError> {
+        self.inner.get_db_handler().retrieve_migrations().await
+    }
+
+    async fn migrate(&self) -> Result<Vec<MigrationModel>, DatabaseError> {
+        self.inner.get_db_handler().migrate().await
+    }
+
+    async fn retrieve_from_id(&self, id: i64) -> Result<AgendaModel, DatabaseError> {
+        if !read_from_replica() {
+            return self.inner.get_db_handler().retrieve_from_id(id).await;
+        }
+
+        let (cached, generation) = {
+            let mut cache = self.cache();
+            (cache.get(id), cache.generation)
+        };
+        METRICS.record_cache_lookup(cached.is_some());
+        if let Some(agenda) = cached {
+            return Ok(agenda);
+        }
+
+        let agenda = self.inner.get_db_handler().retrieve_from_id(id).await?;
+        self.cache().insert(agenda.clone(), generation);
+        Ok(agenda)
+    }
+
+    async fn retrieve_from_id_as_of(&self, id: i64, as_of: i64) -> Result<AgendaModel, DatabaseError> {
+        self.inner.get_db_handler().retrieve_from_id_as_of(id, as_of).await
+    }
+
+    async fn retrieve_all(&self, page: i64, items: i64) -> Result<(Vec<AgendaModel>, i64, i64), DatabaseError> {
+        self.inner.get_db_handler().retrieve_all(page, items).await
+    }
+
+    async fn retrieve_after(&self, after_id: i64, items: i64) -> Result<Vec<AgendaModel>, DatabaseError> {
+        self.inner.get_db_handler().retrieve_after(after_id, items).await
+    }
+
+    async fn create_agenda(&self, agenda: AgendaModel, actor: &str, method: &str) -> Result<AgendaModel, DatabaseError> {
+        self.inner.get_db_handler().create_agenda(agenda, actor, method).await
+    }
+
+    async fn update_agenda(&self, id: i64, agenda: AgendaModel, paths: &[String], actor: &str, method: &str) -> Result<AgendaModel, DatabaseError> {
+        let _invalidation = self.invalidation(id);
+        self.inner.get_db_handler().update_agenda(id, agenda, paths, actor, method).await
+    }
+
+    async fn upsert_agenda(&self, agenda: AgendaModel, actor: &str, method: &str) -> Result<AgendaModel, DatabaseError> {
+        let _invalidation = self.invalidation(agenda.id);
+        self.inner.get_db_handler().upsert_agenda(agenda, actor, method).await
+    }
+
+    async fn delete_agenda(&self, id: i64, actor: &str, method: &str) -> Result<(), DatabaseError> {
+        let _invalidation = self.invalidation(id);
+        self.inner.get_db_handler().delete_agenda(id, actor, method).await
+    }
+
+    async fn retrieve_agenda_history(&self, id: i64, page: i64, items: i64) -> Result<(Vec<AuditModel>, i64, i64), DatabaseError> {
+        self.inner.get_db_handler().retrieve_agenda_history(id, page, items).await
+    }
+
+    async fn retrieve_agenda_revision(&self, id: i64, revision: i64) -> Result<AuditModel, DatabaseError> {
+        self.inner.get_db_handler().retrieve_agenda_revision(id, revision).await
+    }
+
+    async fn create_api_key(&self, api_key: ApiKeyModel) -> Result<ApiKeyModel, DatabaseError> {
+        self.inner.get_db_handler().create_api_key(api_key).await
+    }
+
+    async fn retrieve_api_key(&self, id: i64) -> Result<ApiKeyModel, DatabaseError> {
+        self.inner.get_db_handler().retrieve_api_key(id).await
+    }
+
+    async fn retrieve_api_keys(&self, include_revoked: bool) -> Result<Vec<ApiKeyModel>, DatabaseError> {
+        self.inner.get_db_handler().retrieve_api_keys(include_revoked).await
+    }
+
+    async fn revoke_api_key(&self, id: i64) -> Result<ApiKeyModel, DatabaseError> {
+        self.inner.get_db_handler().revoke_api_key(id).await
+    }
+
+    async fn touch_api_key(&self, id: i64) -> Result<(), DatabaseError> {
+        self.inner.get_db_handler().touch_api_key(id).await
+    }
+
+    async fn close(&self) {
+        self.inner.get_db_handler().close().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::database::routing::with_read_from_replica;
+    use super::*;
+
+    fn agenda(id: i64, name: &str) -> AgendaModel {
+        AgendaModel {id, name: name.to_string(), phone: "1".to_string(), email: "a@b.c".to_string()}
+    }
+
+    #[tokio::test]
+    async fn test_lru_cache_eviction() {
+        let mut cache = LruCache::new(2, Duration::from_secs(60));
+
+        cache.insert(agenda(1, "one"), 0);
+        cache.insert(agenda(2, "two"), 0);
+        assert!(cache.get(1).is_some());
+        cache.insert(agenda(3, "three"), 0);
+
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(3).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_lru_cache_ttl() {
+        let mut cache = LruCache::new(2, Duration::from_secs(0));
+
+        cache.insert(agenda(1, "one"), 0);
+
+        assert!(cache.get(1).is_none());
+        assert!(cache.entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lru_cache_invalidate() {
+        let mut cache = LruCache::new(2, Duration::from_secs(60));
+
+        cache.insert(agenda(1, "one"), 0);
+        cache.invalidate(1);
+        assert!(cache.get(1).is_none());
+
+        // A read that started before the invalidation cannot fill the cache
+        cache.insert(agenda(1, "one"), 0);
+        assert!(cache.get(1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cached_database() {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let database = CachedDatabase::new(DBLayers::new_db_handler().await.unwrap(), 10, Duration::from_secs(60));
+        let created = database.create_agenda(agenda(0, "cached"), "actor", "CreateAgenda").await.unwrap();
+
+        with_read_from_replica(true, async {
+            assert_eq!(database.retrieve_from_id(created.id).await.map(|a| a.name), Ok("cached".to_string()));
+            assert!(database.cache().entries.contains_key(&created.id));
+
+            database.update_agenda(created.id, agenda(created.id, "updated"), &[], "actor", "UpdateAgenda").await.unwrap();
+            assert!(!database.cache().entries.contains_key(&created.id));
+            assert_eq!(database.retrieve_from_id(created.id).await.map(|a| a.name), Ok("updated".to_string()));
+
+            // A read which started before a write cannot fill the cache once the write is done
+            let generation = database.cache().generation;
+            database.update_agenda(created.id, agenda(created.id, "again"), &[], "actor", "UpdateAgenda").await.unwrap();
+            database.cache().insert(agenda(created.id, "updated"), generation);
+            assert!(!database.cache().entries.contains_key(&created.id));
+
+            // A cancelled write may still be applied, it invalidates the cache as well
+            assert_eq!(database.retrieve_from_id(created.id).await.map(|a| a.name), Ok("again".to_string()));
+            let cancelled = tokio::time::timeout(Duration::ZERO, database.update_agenda(created.id, agenda(created.id, "cancelled"), &[], "actor", "UpdateAgenda")).await;
+            assert!(cancelled.is_err());
+            assert!(!database.cache().entries.contains_key(&created.id));
+
+            database.delete_agenda(created.id, "actor", "DeleteAgenda").await.unwrap();
+            assert_eq!(database.retrieve_from_id(created.id).await, Err(DatabaseError::NotFoundError {id: created.id}));
+        }).await;
+    }
+}
@@ -1,26 +1,46 @@
 use std::env;
 use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 use crate::database::Database;
+use crate::database::cache::CachedDatabase;
 use crate::database::postgres::PostgresDB;
 
 #[derive(Debug, Clone)]
 pub enum DBLayers {
     Postgres(PostgresDB),
+    Cached(Arc<CachedDatabase>),
 }
 
 
 impl DBLayers {
     pub async fn new_db_handler() -> Result<Self, Box<dyn Error>> {
         let db_type = env::var("DATABASE_TYPE")?;
-        match db_type.as_str() {
-            "postgres" => Ok(DBLayers::Postgres(PostgresDB::new().await?)),
-            _ => Err(Box::<dyn Error>::from(format!("unknown database type {db_type}"))),
+        let database = match db_type.as_str() {
+            "postgres" => DBLayers::Postgres(PostgresDB::new().await?),
+            _ => return Err(Box::<dyn Error>::from(format!("unknown database type {db_type}"))),
+        };
+
+        // The cache is disabled unless it has a capacity
+        let cache_capacity = env::var("DATABASE_CACHE_CAPACITY").ok()
+            .map(|capacity| capacity.parse::<usize>().map_err(|err| format!("DATABASE_CACHE_CAPACITY: {err}")))
+            .transpose()?
+            .unwrap_or(0);
+        let cache_ttl = env::var("DATABASE_CACHE_TTL_SECS").ok()
+            .map(|ttl| ttl.parse::<u64>().map_err(|err| format!("DATABASE_CACHE_TTL_SECS: {err}")))
+            .transpose()?
+            .unwrap_or(30);
+
+        match cache_capacity {
+            0 => Ok(database),
+            capacity => Ok(DBLayers::Cached(Arc::new(CachedDatabase::new(database, capacity, Duration::from_secs(cache_ttl))))),
         }
     }
     
-    pub fn get_db_handler(&self) -> &impl Database {
+    pub fn get_db_handler(&self) -> &dyn Database {
         match self {
             DBLayers::Postgres(db) => db,
+            DBLayers::Cached(db) => db.as_ref(),
         }
     }
 }
@@ -43,6 +63,18 @@ mod tests {
         assert!(matches!(dbl, DBLayers::Postgres(_)));
     }
 
+    #[tokio::test]
+    async fn test_new_db_handler_cached() {
+        env::set_var("DATABASE_TYPE", "postgres");
+        env::set_var("DATABASE_CACHE_CAPACITY", "100");
+
+        let result = DBLayers::new_db_handler().await;
+
+        assert!(matches!(result, Ok(DBLayers::Cached(_))));
+
+        env::remove_var("DATABASE_CACHE_CAPACITY");
+    }
+
     #[tokio::test]
     async fn test_new_db_handler_unknown_type() {
         env::set_var("DATABASE_TYPE", "invalid_type");
@@ -0,0 +1,40 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+
+tokio::task_local! {
+    // Set per request by the deadline middleware so queries do not outlive the request
+    static DEADLINE: Instant;
+}
+
+pub async fn with_deadline<F: Future>(deadline: Instant, future: F) -> F::Output {
+    DEADLINE.scope(deadline, future).await
+}
+
+// The deadline of the current request, for work that outlives its handler like a streamed response
+pub fn current() -> Option<Instant> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+// Time left before the current request expires, None outside a request
+pub fn remaining() -> Option<Duration> {
+    DEADLINE.try_with(|deadline| deadline.saturating_duration_since(Instant::now())).ok()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_remaining() {
+        assert_eq!(remaining(), None);
+        assert_eq!(current(), None);
+
+        let left = with_deadline(Instant::now() + Duration::from_secs(10), async { remaining() }).await;
+        assert!(left.is_some_and(|left| left > Duration::from_secs(9)));
+
+        let left = with_deadline(Instant::now(), async { remaining() }).await;
+        assert_eq!(left, Some(Duration::ZERO));
+    }
+}
@@ -8,18 +8,46 @@ pub enum DatabaseError {
     ConnectionError,
     NotFoundError{id: i64},
     AlreadyExists{error: String},
+    InvalidArgument{error: String},
+    TransientError{error: String},
+    TimeoutError{error: String},
     UnimplementedError,
     UnknownError{error: String},
 }
 
 
 
+impl DatabaseError {
+    // Short, stable name of the variant, used to label metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DatabaseError::ConnectionError => "connection",
+            DatabaseError::NotFoundError{..} => "not_found",
+            DatabaseError::AlreadyExists{..} => "already_exists",
+            DatabaseError::InvalidArgument{..} => "invalid_argument",
+            DatabaseError::TransientError{..} => "transient",
+            DatabaseError::TimeoutError{..} => "timeout",
+            DatabaseError::UnimplementedError => "unimplemented",
+            DatabaseError::UnknownError{..} => "unknown",
+        }
+    }
+
+    // Errors that may not happen again if the same operation is retried
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, DatabaseError::ConnectionError | DatabaseError::TransientError{..})
+    }
+}
+
+
 // Implement fmt::Display trait in order to be a std::error::Error type
 impl fmt::Display for DatabaseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             DatabaseError::NotFoundError{id} => write!(f, "the element with id {} does not exists", id),
             DatabaseError::AlreadyExists{error} => write!(f, "the element with id {} already exists", error),
+            DatabaseError::InvalidArgument{error} => write!(f, "invalid argument: {}", error),
+            DatabaseError::TransientError{error} => write!(f, "transient error: {}", error),
+            DatabaseError::TimeoutError{error} => write!(f, "timeout: {}", error),
             DatabaseError::UnknownError{error} => write!(f, "internal error: {}", error),
             DatabaseError::ConnectionError => write!(f, "connection error with database"),
             DatabaseError::UnimplementedError => write!(f, "unimplemented error"),
@@ -46,6 +74,11 @@ impl From<DatabaseError> for Status {
         match err {
             DatabaseError::NotFoundError {id: _id} => Status::new(Code::NotFound, err.to_string()),
             DatabaseError::AlreadyExists {error} => Status::new(Code::AlreadyExists, error),
+            DatabaseError::InvalidArgument {error} => Status::new(Code::InvalidArgument, error),
+            // Aborted tells the client the whole operation can be retried
+            DatabaseError::TransientError {error} => Status::new(Code::Aborted, error),
+            // The query was cancelled because the request deadline expired
+            DatabaseError::TimeoutError {error} => Status::new(Code::DeadlineExceeded, error),
             DatabaseError::UnknownError {error} => Status::new(Code::Internal, error),
             DatabaseError::ConnectionError => Status::new(Code::Unavailable, err.to_string()),
             DatabaseError::UnimplementedError => Status::new(Code::Unimplemented, err.to_string()),
@@ -64,6 +97,45 @@ mod tests {
         assert_eq!(error.to_string(), "the element with id 1 does not exists");
     }
 
+    #[tokio::test]
+    async fn test_database_error_kind() {
+        assert_eq!(DatabaseError::NotFoundError{id: 1}.kind(), "not_found");
+        assert_eq!(DatabaseError::ConnectionError.kind(), "connection");
+    }
+
+    #[tokio::test]
+    async fn test_database_error_is_retryable() {
+        assert!(DatabaseError::ConnectionError.is_retryable());
+        assert!(DatabaseError::TransientError{error: "deadlock".to_string()}.is_retryable());
+        assert!(!DatabaseError::TimeoutError{error: "canceled".to_string()}.is_retryable());
+        assert!(!DatabaseError::NotFoundError{id: 1}.is_retryable());
+        assert!(!DatabaseError::UnknownError{error: "error".to_string()}.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_database_error_into_invalid_argument() {
+        let error = DatabaseError::InvalidArgument{error: "error".to_string()};
+        let status = Status::from(error);
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "error");
+    }
+
+    #[tokio::test]
+    async fn test_database_error_into_transient() {
+        let error = DatabaseError::TransientError{error: "error".to_string()};
+        let status = Status::from(error);
+        assert_eq!(status.code(), Code::Aborted);
+        assert_eq!(status.message(), "error");
+    }
+
+    #[tokio::test]
+    async fn test_database_error_into_timeout() {
+        let error = DatabaseError::TimeoutError{error: "error".to_string()};
+        let status = Status::from(error);
+        assert_eq!(status.code(), Code::DeadlineExceeded);
+        assert_eq!(status.message(), "error");
+    }
+
     #[tokio::test]
     async fn test_database_error_from() {
         let error = std::io::Error::new(std::io::ErrorKind::Other, "test");
@@ -1,11 +1,16 @@
+pub mod cache;
 pub mod database_object;
+pub mod deadline;
 pub mod error;
 mod postgres;
+mod retry;
+pub mod routing;
 
 use std::error::Error;
+use std::fmt::Debug;
 use tonic::async_trait;
 use crate::database::error::DatabaseError;
-use crate::model::AgendaModel;
+use crate::model::{AgendaModel, ApiKeyModel, AuditModel, MigrationModel};
 
 
 #[macro_export]
@@ -26,37 +31,78 @@ macro_rules! trace_and_handle_error_database {
         // Match on the result to handle success and error cases
         match &result {
             Ok(_) => {
-                tracing::info!("Function executed successfully");
                 current_span.record("success", &true);
+                tracing::info!("Function executed successfully");
 
             },
             
             Err(DatabaseError::UnknownError{ error: err }) => {
-                tracing::error!("Error in function: {}", err);
                 current_span.record("error", &format!("{}", err));
+                tracing::error!("Error in function: {}", err);
             },
             
             Err(err) => {
-                tracing::warn!("Error in function: {}", err);
                 current_span.record("warn", &format!("{}", err));
+                tracing::warn!("Error in function: {}", err);
             }
         }
+
+        if let Err(err) = &result {
+            $crate::otel::METRICS.record_database_error(err);
+        }
         result
     }};
 }
 
 #[async_trait]
-pub trait Database {
+pub trait Database: Send + Sync + Debug {
+    // Applies the pending migrations
     async fn init_database(&self) -> Result<(), Box<dyn Error>>;
+
+    // Every migration known to this build or recorded in the database, ordered by version
+    async fn retrieve_migrations(&self) -> Result<Vec<MigrationModel>, DatabaseError>;
+
+    // Applies the pending migrations in order and returns them
+    async fn migrate(&self) -> Result<Vec<MigrationModel>, DatabaseError>;
+
     async fn retrieve_from_id(&self, id: i64) -> Result<AgendaModel, DatabaseError>;
 
+    async fn retrieve_from_id_as_of(&self, id: i64, as_of: i64) -> Result<AgendaModel, DatabaseError>;
+
     async fn retrieve_all(&self, page: i64, items: i64) -> Result<(Vec<AgendaModel>, i64, i64), DatabaseError>;
 
-    async fn create_agenda(&self, agenda: AgendaModel) -> Result<AgendaModel, DatabaseError>;
+    // The next `items` agendas by id after `after_id`, so walking the table is not thrown off by
+    // agendas created or deleted meanwhile
+    async fn retrieve_after(&self, after_id: i64, items: i64) -> Result<Vec<AgendaModel>, DatabaseError>;
+
+    // Every change to an agenda is recorded in its history, as made by `actor` through `method`, in
+    // the same transaction as the change
+    async fn create_agenda(&self, agenda: AgendaModel, actor: &str, method: &str) -> Result<AgendaModel, DatabaseError>;
+
+    // Only the fields named by the update mask are changed, all of them when it is empty
+    async fn update_agenda(&self, id: i64, agenda: AgendaModel, paths: &[String], actor: &str, method: &str) -> Result<AgendaModel, DatabaseError>;
+
+    async fn upsert_agenda(&self, agenda: AgendaModel, actor: &str, method: &str) -> Result<AgendaModel, DatabaseError>;
+
+    async fn delete_agenda(&self, id: i64, actor: &str, method: &str) -> Result<(), DatabaseError>;
+
+    async fn retrieve_agenda_history(&self, id: i64, page: i64, items: i64) -> Result<(Vec<AuditModel>, i64, i64), DatabaseError>;
+
+    async fn retrieve_agenda_revision(&self, id: i64, revision: i64) -> Result<AuditModel, DatabaseError>;
+
+    async fn create_api_key(&self, api_key: ApiKeyModel) -> Result<ApiKeyModel, DatabaseError>;
+
+    async fn retrieve_api_key(&self, id: i64) -> Result<ApiKeyModel, DatabaseError>;
+
+    async fn retrieve_api_keys(&self, include_revoked: bool) -> Result<Vec<ApiKeyModel>, DatabaseError>;
+
+    // Revoking a key twice keeps the first revocation time
+    async fn revoke_api_key(&self, id: i64) -> Result<ApiKeyModel, DatabaseError>;
 
-    async fn update_agenda(&self, id: i64, agenda: AgendaModel) -> Result<AgendaModel, DatabaseError>;
+    async fn touch_api_key(&self, id: i64) -> Result<(), DatabaseError>;
 
-    async fn delete_agenda(&self, id: i64) -> Result<(), DatabaseError>;
+    // Waits for the checked out connections to be returned, then closes every connection
+    async fn close(&self);
 }
 
 
@@ -0,0 +1,69 @@
+// Applied in order by version and recorded in schema_migrations. A released migration is never
+// changed, later schema changes are new migrations. The first ones use IF NOT EXISTS so databases
+// created before migrations were tracked adopt them without changes.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+// Held for the migration transaction, so servers starting together migrate one after the other
+pub const MIGRATIONS_LOCK_ID: i64 = 0x6167656e6461;
+
+pub const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    name varchar NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);";
+
+pub const MIGRATIONS: [Migration; 3] = [
+    Migration {
+        version: 1,
+        name: "create agendas",
+        statements: &["CREATE TABLE IF NOT EXISTS my_table (
+	id BIGSERIAL PRIMARY KEY,
+	name varchar NOT NULL,
+    phone varchar NOT NULL,
+    email varchar NOT NULL,
+	CONSTRAINT my_table_pk_1 UNIQUE (name)
+);"],
+    },
+    // The history table is append-only: updates and deletes on it are silently discarded
+    Migration {
+        version: 2,
+        name: "create agenda history",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS my_table_history (
+    revision BIGSERIAL PRIMARY KEY,
+    agenda_id BIGINT NOT NULL,
+    actor varchar NOT NULL,
+    method varchar NOT NULL,
+    before_name varchar,
+    before_phone varchar,
+    before_email varchar,
+    after_name varchar,
+    after_phone varchar,
+    after_email varchar,
+    changed_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp()
+);",
+            "CREATE INDEX IF NOT EXISTS my_table_history_agenda_id ON my_table_history (agenda_id, revision);",
+            "CREATE OR REPLACE RULE my_table_history_no_update AS ON UPDATE TO my_table_history DO INSTEAD NOTHING;",
+            "CREATE OR REPLACE RULE my_table_history_no_delete AS ON DELETE TO my_table_history DO INSTEAD NOTHING;",
+        ],
+    },
+    // Only the argon2 hash of each key secret is stored
+    Migration {
+        version: 3,
+        name: "create api keys",
+        statements: &["CREATE TABLE IF NOT EXISTS api_keys (
+    id BIGSERIAL PRIMARY KEY,
+    name varchar NOT NULL,
+    secret_hash varchar NOT NULL,
+    scopes varchar[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT clock_timestamp(),
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);"],
+    },
+];
@@ -0,0 +1,98 @@
+use std::future::Future;
+use std::time::Duration;
+use rand::Rng;
+use crate::database::error::DatabaseError;
+
+
+// Total attempts for a read, including the first one
+fn max_read_attempts() -> u32 {
+    std::env::var("DATABASE_READ_ATTEMPTS")
+        .ok()
+        .and_then(|attempts| attempts.parse::<u32>().ok())
+        .unwrap_or(3)
+        .max(1)
+}
+
+// Full jitter: a random wait between zero and an exponential cap, so retrying clients spread out
+fn jittered_backoff(attempt: u32) -> Duration {
+    let cap = 50u64.saturating_mul(1 << attempt.min(10)).min(2000);
+    Duration::from_millis(rand::thread_rng().gen_range(0..=cap))
+}
+
+// Only for idempotent operations, a retried write could be applied twice
+pub async fn retry_read<T, F, Fut>(operation: F) -> Result<T, DatabaseError>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, DatabaseError>>,
+{
+    let attempts = max_read_attempts();
+    let mut attempt = 1;
+
+    loop {
+        match operation().await {
+            Err(err) if err.is_retryable() && attempt < attempts => {
+                let backoff = jittered_backoff(attempt);
+                tracing::warn!("Retrying read in {:?} (attempt {} of {}): {}", backoff, attempt, attempts, err);
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retry_read_retryable() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_read(|| async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(DatabaseError::ConnectionError),
+                1 => Err(DatabaseError::TransientError{error: "deadlock detected".to_string()}),
+                _ => Ok(1),
+            }
+        }).await;
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_read_gives_up() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_read(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), DatabaseError>(DatabaseError::ConnectionError)
+        }).await;
+
+        assert_eq!(result, Err(DatabaseError::ConnectionError));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_read_not_retryable() {
+        let calls = AtomicU32::new(0);
+
+        let result = retry_read(|| async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err::<(), DatabaseError>(DatabaseError::NotFoundError{id: 1})
+        }).await;
+
+        assert_eq!(result, Err(DatabaseError::NotFoundError{id: 1}));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_jittered_backoff() {
+        for attempt in 1..20 {
+            assert!(jittered_backoff(attempt) <= Duration::from_millis(2000));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+tokio::task_local! {
+    // Set per request by the read routing middleware, code outside a request always uses the primary
+    static READ_FROM_REPLICA: bool;
+}
+
+pub async fn with_read_from_replica<F: Future>(read_from_replica: bool, future: F) -> F::Output {
+    READ_FROM_REPLICA.scope(read_from_replica, future).await
+}
+
+pub fn read_from_replica() -> bool {
+    READ_FROM_REPLICA.try_with(|read_from_replica| *read_from_replica).unwrap_or(false)
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplicaBalancing {
+    RoundRobin,
+    LeastConnections,
+}
+
+impl ReplicaBalancing {
+    pub fn from_env() -> Self {
+        match std::env::var("DATABASE_REPLICA_BALANCING").unwrap_or("round_robin".to_string()).as_str() {
+            "least_connections" => ReplicaBalancing::LeastConnections,
+            _ => ReplicaBalancing::RoundRobin,
+        }
+    }
+}
+
+
+// Picks the replica for the next read, `in_use` has the connections each replica has checked out
+#[derive(Debug)]
+pub struct ReplicaSelector {
+    balancing: ReplicaBalancing,
+    next: AtomicUsize,
+}
+
+impl ReplicaSelector {
+    pub fn new(balancing: ReplicaBalancing) -> Self {
+        ReplicaSelector {
+            balancing,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn select(&self, in_use: &[usize]) -> Option<usize> {
+        if in_use.is_empty() {
+            return None;
+        }
+        match self.balancing {
+            ReplicaBalancing::RoundRobin => Some(self.next.fetch_add(1, Ordering::Relaxed) % in_use.len()),
+            // Ties go to the replica after the last one picked, so idle replicas share the load
+            ReplicaBalancing::LeastConnections => {
+                let start = self.next.fetch_add(1, Ordering::Relaxed);
+                (0..in_use.len())
+                    .map(|offset| (start + offset) % in_use.len())
+                    .min_by_key(|index| in_use[*index])
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_from_replica_scope() {
+        assert!(!read_from_replica());
+        assert!(with_read_from_replica(true, async { read_from_replica() }).await);
+        assert!(!with_read_from_replica(false, async { read_from_replica() }).await);
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let selector = ReplicaSelector::new(ReplicaBalancing::RoundRobin);
+
+        let selected: Vec<Option<usize>> = (0..4).map(|_| selector.select(&[5, 0, 0])).collect();
+
+        assert_eq!(selected, vec![Some(0), Some(1), Some(2), Some(0)]);
+        assert_eq!(selector.select(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_least_connections() {
+        let selector = ReplicaSelector::new(ReplicaBalancing::LeastConnections);
+
+        assert_eq!(selector.select(&[5, 1, 3]), Some(1));
+        assert_eq!(selector.select(&[0, 2, 0]), Some(2));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::format::Records;
+use crate::model::AgendaModel;
+
+const COLUMNS: [&str; 4] = ["id", "name", "email", "phone"];
+
+// Column names written by spreadsheets and by the contact exports of phones and mail clients, they
+// are compared ignoring case. The id column is only written, imported agendas get new ids.
+const NAME_COLUMNS: [&str; 4] = ["name", "full name", "display name", "fn"];
+const GIVEN_NAME_COLUMNS: [&str; 3] = ["first name", "given name", "given"];
+const FAMILY_NAME_COLUMNS: [&str; 3] = ["last name", "family name", "surname"];
+const EMAIL_COLUMNS: [&str; 7] = ["email", "e-mail", "email address", "e-mail address", "e-mail 1 - value", "email 1", "mail"];
+const PHONE_COLUMNS: [&str; 9] = ["phone", "telephone", "tel", "phone number", "mobile", "mobile phone", "phone 1 - value", "home phone", "business phone"];
+
+
+#[derive(Debug, PartialEq)]
+struct Columns {
+    name: Option<usize>,
+    given_name: Option<usize>,
+    family_name: Option<usize>,
+    email: Option<usize>,
+    phone: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &csv::StringRecord) -> Result<Self, String> {
+        // Spreadsheets saving as UTF-8 start the file with a byte order mark
+        let find = |names: &[&str]| header.iter().position(|column| {
+            names.contains(&column.trim_start_matches('\u{feff}').trim().to_lowercase().as_str())
+        });
+        let columns = Columns {
+            name: find(&NAME_COLUMNS),
+            given_name: find(&GIVEN_NAME_COLUMNS),
+            family_name: find(&FAMILY_NAME_COLUMNS),
+            email: find(&EMAIL_COLUMNS),
+            phone: find(&PHONE_COLUMNS),
+        };
+        if columns.name.is_none() && columns.given_name.is_none() && columns.family_name.is_none() {
+            return Err(format!("the header has no name column, expected one of: {}", NAME_COLUMNS.join(", ")));
+        }
+        Ok(columns)
+    }
+
+    // Rows may be shorter than the header, spreadsheets leave out trailing empty cells
+    fn agenda(&self, record: &csv::StringRecord) -> Result<AgendaModel, String> {
+        let field = |column: Option<usize>| column
+            .and_then(|column| record.get(column))
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let name = match field(self.name) {
+            name if name.is_empty() => [field(self.given_name), field(self.family_name)]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<String>>()
+                .join(" "),
+            name => name,
+        };
+        if name.is_empty() {
+            return Err("empty name".to_string());
+        }
+        Ok(AgendaModel {id: 0, name, email: field(self.email), phone: field(self.phone)})
+    }
+}
+
+
+pub fn write_agendas(agendas: &[AgendaModel], header: bool) -> Result<Vec<u8>, String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if header {
+        writer.write_record(COLUMNS).map_err(|err| err.to_string())?;
+    }
+    for agenda in agendas {
+        writer.write_record([agenda.id.to_string().as_str(), &agenda.name, &agenda.email, &agenda.phone])
+            .map_err(|err| err.to_string())?;
+    }
+    writer.into_inner().map_err(|err| err.to_string())
+}
+
+// Records are numbered by the line they start on, the header being line 1, as spreadsheets show them
+pub fn read_agendas(data: &[u8]) -> Result<Records, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    let columns = Columns::from_header(reader.headers().map_err(|err| format!("invalid CSV header: {err}"))?)?;
+
+    let mut records = Vec::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        let (line, agenda) = match reader.read_record(&mut record) {
+            Ok(false) => break,
+            Ok(true) => (record.position().map(|position| position.line()), columns.agenda(&record)),
+            Err(err) => (err.position().map(|position| position.line()), Err(format!("invalid CSV: {err}"))),
+        };
+        records.push((line.unwrap_or_default() as usize, agenda));
+    }
+    Ok(records)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_header_mapping() {
+        let data = "\u{feff}First Name,Last Name,E-mail 1 - Value,Mobile Phone\nAda,Lovelace,ada@example.com,555\n";
+        let records = read_agendas(data.as_bytes()).unwrap();
+        assert_eq!(records, vec![
+            (2, Ok(AgendaModel {id: 0, name: "Ada Lovelace".into(), email: "ada@example.com".into(), phone: "555".into()})),
+        ]);
+
+        let header = csv::StringRecord::from(vec!["Phone", "EMAIL", "Id"]);
+        assert!(Columns::from_header(&header).unwrap_err().starts_with("the header has no name column"));
+        assert!(read_agendas(b"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_row_errors() {
+        let data = b"id,name,email,phone\n7,Ada,ada@example.com,555\n8,,nameless@example.com,556\n9,Grace\n10,\xff,bad@example.com,557\n";
+        let records = read_agendas(data).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].1.as_ref().unwrap().id, 0);
+        assert_eq!(records[1], (3, Err("empty name".to_string())));
+        assert_eq!(records[2], (4, Ok(AgendaModel {id: 0, name: "Grace".into(), email: "".into(), phone: "".into()})));
+        assert_eq!(records[3].0, 5);
+        assert!(records[3].1.as_ref().unwrap_err().starts_with("invalid CSV"));
+    }
+
+    #[tokio::test]
+    async fn test_write_agendas() {
+        let agenda = AgendaModel {id: 3, name: "Smith, John".into(), email: "j@example.com".into(), phone: "555".into()};
+        let data = write_agendas(std::slice::from_ref(&agenda), true).unwrap();
+        assert_eq!(String::from_utf8(data).unwrap(), "id,name,email,phone\n3,\"Smith, John\",j@example.com,555\n");
+        assert_eq!(write_agendas(&[agenda], false).unwrap(), b"3,\"Smith, John\",j@example.com,555\n");
+    }
+}
@@ -0,0 +1,84 @@
+mod csv;
+mod vcard;
+
+use crate::agenda::FileFormat as ProtoFileFormat;
+use crate::model::AgendaModel;
+
+
+// The agendas of a file by record number, or why each record is not one
+pub type Records = Vec<(usize, Result<AgendaModel, String>)>;
+
+
+// The files agendas are exchanged with spreadsheets and phones as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Csv,
+    VCard3,
+    VCard4,
+}
+
+impl FileFormat {
+    pub fn from_proto(format: i32) -> Result<Self, String> {
+        match ProtoFileFormat::try_from(format) {
+            Ok(ProtoFileFormat::Csv) => Ok(FileFormat::Csv),
+            Ok(ProtoFileFormat::Vcard3) => Ok(FileFormat::VCard3),
+            Ok(ProtoFileFormat::Vcard4) => Ok(FileFormat::VCard4),
+            _ => Err(format!("unsupported file format {format}")),
+        }
+    }
+}
+
+
+// Files are written a page at a time, the CSV header only goes before the first one
+pub fn write_agendas(format: FileFormat, agendas: &[AgendaModel], first: bool) -> Result<Vec<u8>, String> {
+    match format {
+        FileFormat::Csv => self::csv::write_agendas(agendas, first),
+        FileFormat::VCard3 => Ok(vcard::write_agendas(agendas, vcard::Version::V3)),
+        FileFormat::VCard4 => Ok(vcard::write_agendas(agendas, vcard::Version::V4)),
+    }
+}
+
+// Every record is read, the ones which are not agendas come with their error. The whole file is an
+// error only when nothing can be read from it, like a CSV header without a name column.
+pub fn read_agendas(format: FileFormat, data: &[u8]) -> Result<Records, String> {
+    match format {
+        FileFormat::Csv => self::csv::read_agendas(data),
+        // Phones do not always export the version they were asked for, so either one is accepted
+        FileFormat::VCard3 | FileFormat::VCard4 => vcard::read_agendas(data),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_format() {
+        assert_eq!(FileFormat::from_proto(ProtoFileFormat::Csv as i32), Ok(FileFormat::Csv));
+        assert_eq!(FileFormat::from_proto(ProtoFileFormat::Vcard4 as i32), Ok(FileFormat::VCard4));
+        assert!(FileFormat::from_proto(ProtoFileFormat::Unspecified as i32).is_err());
+        assert!(FileFormat::from_proto(42).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let agendas = [
+            AgendaModel {id: 1, name: "Ada Lovelace".into(), email: "ada@example.com".into(), phone: "+44 20 7946 0000".into()},
+            AgendaModel {id: 2, name: "Smith, John; Jr.".into(), email: "".into(), phone: "+1-555-0100".into()},
+        ];
+        for format in [FileFormat::Csv, FileFormat::VCard3, FileFormat::VCard4] {
+            let mut data = write_agendas(format, &agendas[..1], true).unwrap();
+            data.extend(write_agendas(format, &agendas[1..], false).unwrap());
+
+            let read = read_agendas(format, &data).unwrap().into_iter()
+                .map(|(_, agenda)| agenda.map(|agenda| (agenda.name, agenda.email, agenda.phone)))
+                .collect::<Result<Vec<_>, String>>()
+                .unwrap();
+            let expected = agendas.iter()
+                .map(|agenda| (agenda.name.clone(), agenda.email.clone(), agenda.phone.clone()))
+                .collect::<Vec<_>>();
+            assert_eq!(read, expected, "{format:?}");
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::format::Records;
+use crate::model::AgendaModel;
+
+// Longer content lines are folded, the continuations start with a space
+const LINE_LENGTH: usize = 75;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    V3,
+    V4,
+}
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match character {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut characters = value.chars();
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            unescaped.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// Folds at 75 octets without splitting a character
+fn push_line(file: &mut String, line: &str) {
+    let mut start = 0;
+    let mut length = LINE_LENGTH;
+    while line.len() - start > length {
+        let mut end = start + length;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        file.push_str(&line[start..end]);
+        file.push_str("\r\n ");
+        start = end;
+        length = LINE_LENGTH - 1;
+    }
+    file.push_str(&line[start..]);
+    file.push_str("\r\n");
+}
+
+// 4.0 prefers tel URIs, which can only hold global numbers without a phone-context
+fn is_global_number(phone: &str) -> bool {
+    phone.strip_prefix('+').is_some_and(|number| {
+        number.starts_with(|character: char| character.is_ascii_digit())
+            && number.chars().all(|character| character.is_ascii_digit() || "-.()".contains(character))
+    })
+}
+
+pub fn write_agendas(agendas: &[AgendaModel], version: Version) -> Vec<u8> {
+    let mut file = String::new();
+    for agenda in agendas {
+        push_line(&mut file, "BEGIN:VCARD");
+        push_line(&mut file, match version {
+            Version::V3 => "VERSION:3.0",
+            Version::V4 => "VERSION:4.0",
+        });
+        push_line(&mut file, &format!("FN:{}", escape(&agenda.name)));
+        // 3.0 requires N, the name is not split so it all goes in the given name
+        push_line(&mut file, &format!("N:;{};;;", escape(&agenda.name)));
+        if !agenda.email.is_empty() {
+            push_line(&mut file, &match version {
+                Version::V3 => format!("EMAIL;TYPE=INTERNET:{}", escape(&agenda.email)),
+                Version::V4 => format!("EMAIL:{}", escape(&agenda.email)),
+            });
+        }
+        if !agenda.phone.is_empty() {
+            push_line(&mut file, &match version {
+                Version::V3 => format!("TEL;TYPE=VOICE:{}", escape(&agenda.phone)),
+                Version::V4 if is_global_number(&agenda.phone) => format!("TEL;VALUE=uri:tel:{}", agenda.phone),
+                Version::V4 => format!("TEL;VALUE=text:{}", escape(&agenda.phone)),
+            });
+        }
+        push_line(&mut file, "END:VCARD");
+    }
+    file.into_bytes()
+}
+
+
+// Content lines are "group.NAME;PARAM=value:value", the group is dropped
+#[derive(Debug, PartialEq)]
+struct Property {
+    name: String,
+    parameters: Vec<(String, String)>,
+    value: String,
+}
+
+// Splits on the separator outside double quotes, which parameter values may use
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut start = 0;
+    for (index, character) in text.char_indices() {
+        if character == '"' {
+            quoted = !quoted;
+        } else if character == separator && !quoted {
+            parts.push(&text[start..index]);
+            start = index + 1;
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        let mut quoted = false;
+        let (colon, _) = line.char_indices().find(|(_, character)| {
+            quoted ^= *character == '"';
+            *character == ':' && !quoted
+        })?;
+        let parts = split_unquoted(&line[..colon], ';');
+        let name = parts[0].rsplit('.').next().unwrap_or_default().trim().to_uppercase();
+        // 2.1 style parameters have no name, they are types
+        let parameters = parts[1..].iter()
+            .map(|parameter| match parameter.split_once('=') {
+                Some((name, value)) => (name.trim().to_uppercase(), value.trim_matches('"').to_string()),
+                None => ("TYPE".to_string(), parameter.to_string()),
+            })
+            .collect();
+        Some(Property {name, parameters, value: line[colon + 1..].to_string()})
+    }
+
+    fn is_preferred(&self) -> bool {
+        self.parameters.iter().any(|(name, value)| {
+            name == "PREF" || (name == "TYPE" && value.split(',').any(|kind| kind.trim().eq_ignore_ascii_case("pref")))
+        })
+    }
+}
+
+
+// The first value of a property is kept, unless a later one is marked as preferred
+#[derive(Debug, Default)]
+struct Card {
+    version: Option<String>,
+    name: Option<String>,
+    email: Option<(bool, String)>,
+    phone: Option<(bool, String)>,
+}
+
+impl Card {
+    fn add(&mut self, property: Property) {
+        let keep = |current: &Option<(bool, String)>| current.as_ref()
+            .is_some_and(|(preferred, _)| *preferred || !property.is_preferred());
+        match property.name.as_str() {
+            "VERSION" => self.version = Some(property.value.trim().to_string()),
+            "FN" if self.name.is_none() => self.name = Some(unescape(&property.value)),
+            "EMAIL" if !keep(&self.email) => self.email = Some((property.is_preferred(), unescape(&property.value))),
+            "TEL" if !keep(&self.phone) => {
+                let value = property.value.trim();
+                let phone = match value.get(..4) {
+                    Some(scheme) if scheme.eq_ignore_ascii_case("tel:") => value[4..].to_string(),
+                    _ => unescape(value),
+                };
+                self.phone = Some((property.is_preferred(), phone));
+            }
+            _ => {}
+        }
+    }
+
+    fn agenda(self) -> Result<AgendaModel, String> {
+        match self.version.as_deref() {
+            Some("3.0" | "4.0") => {}
+            Some(version) => return Err(format!("unsupported vCard version {version}, expected 3.0 or 4.0")),
+            None => return Err("missing VERSION".to_string()),
+        }
+        let name = self.name.map(|name| name.trim().to_string()).unwrap_or_default();
+        if name.is_empty() {
+            return Err("missing FN".to_string());
+        }
+        let value = |property: Option<(bool, String)>| property.map(|(_, value)| value.trim().to_string()).unwrap_or_default();
+        Ok(AgendaModel {id: 0, name, email: value(self.email), phone: value(self.phone)})
+    }
+}
+
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// Cards are numbered by their position in the file
+pub fn read_agendas(data: &[u8]) -> Result<Records, String> {
+    let text = std::str::from_utf8(data).map_err(|err| format!("the file is not UTF-8: {err}"))?;
+    let mut records = Vec::new();
+    let mut cards = 0;
+    let mut card: Option<Card> = None;
+    for property in unfold(text).iter().filter_map(|line| Property::parse(line)) {
+        let is_vcard = property.value.trim().eq_ignore_ascii_case("VCARD");
+        match property.name.as_str() {
+            "BEGIN" if is_vcard => {
+                if card.replace(Card::default()).is_some() {
+                    records.push((cards, Err("missing END:VCARD".to_string())));
+                }
+                cards += 1;
+            }
+            "END" if is_vcard => {
+                if let Some(card) = card.take() {
+                    records.push((cards, card.agenda()));
+                }
+            }
+            _ => {
+                if let Some(card) = card.as_mut() {
+                    card.add(property);
+                }
+            }
+        }
+    }
+    if card.is_some() {
+        records.push((cards, Err("missing END:VCARD".to_string())));
+    }
+    Ok(records)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agenda(name: &str, email: &str, phone: &str) -> AgendaModel {
+        AgendaModel {id: 0, name: name.into(), email: email.into(), phone: phone.into()}
+    }
+
+    #[tokio::test]
+    async fn test_write_agendas() {
+        let agendas = [agenda("Smith, John", "j@example.com", "+1-555-0100"), agenda("Ada", "", "555 0101")];
+        let v3 = String::from_utf8(write_agendas(&agendas, Version::V3)).unwrap();
+        assert_eq!(v3, "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Smith\\, John\r\nN:;Smith\\, John;;;\r\nEMAIL;TYPE=INTERNET:j@example.com\r\n\
+            TEL;TYPE=VOICE:+1-555-0100\r\nEND:VCARD\r\nBEGIN:VCARD\r\nVERSION:3.0\r\nFN:Ada\r\nN:;Ada;;;\r\nTEL;TYPE=VOICE:555 0101\r\nEND:VCARD\r\n");
+
+        let v4 = String::from_utf8(write_agendas(&agendas, Version::V4)).unwrap();
+        assert!(v4.contains("VERSION:4.0\r\n"));
+        assert!(v4.contains("\r\nEMAIL:j@example.com\r\n"));
+        assert!(v4.contains("\r\nTEL;VALUE=uri:tel:+1-555-0100\r\n"));
+        assert!(v4.contains("\r\nTEL;VALUE=text:555 0101\r\n"));
+
+        // Folded lines are at most 75 octets and do not split characters
+        let long = write_agendas(&[agenda(&"é".repeat(60), "", "")], Version::V4);
+        let long = String::from_utf8(long).unwrap();
+        assert!(long.split("\r\n").all(|line| line.len() <= LINE_LENGTH));
+        assert_eq!(read_agendas(long.as_bytes()).unwrap()[0].1.as_ref().unwrap().name, "é".repeat(60));
+    }
+
+    #[tokio::test]
+    async fn test_read_agendas() {
+        let data = "BEGIN:VCARD\nVERSION:3.0\nN:Lovelace;Ada;;;\nFN:Ada\n  Lovelace\nitem1.EMAIL;TYPE=INTERNET:ada@home.example\n\
+            item2.EMAIL;TYPE=\"INTERNET,pref\":ada@example.com\nTEL;TYPE=CELL:555\\, 0100\nEND:VCARD\n\
+            BEGIN:VCARD\nVERSION:4.0\nFN:Grace\nTEL;VALUE=uri;PREF=1:tel:+1-555-0101\nEND:VCARD\n\
+            BEGIN:VCARD\nVERSION:2.1\nFN:Old\nEND:VCARD\n\
+            BEGIN:VCARD\nVERSION:4.0\nEMAIL:nameless@example.com\nEND:VCARD\n\
+            BEGIN:VCARD\nVERSION:4.0\nFN:Unterminated\n";
+        let records = read_agendas(data.as_bytes()).unwrap();
+        assert_eq!(records, vec![
+            (1, Ok(agenda("Ada Lovelace", "ada@example.com", "555, 0100"))),
+            (2, Ok(agenda("Grace", "", "+1-555-0101"))),
+            (3, Err("unsupported vCard version 2.1, expected 3.0 or 4.0".to_string())),
+            (4, Err("missing FN".to_string())),
+            (5, Err("missing END:VCARD".to_string())),
+        ]);
+        assert!(read_agendas(b"\xff").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_property() {
+        let property = Property::parse("item1.TEL;TYPE=\"cell;voice\":tel:+1").unwrap();
+        assert_eq!(property.name, "TEL");
+        assert_eq!(property.parameters, vec![("TYPE".to_string(), "cell;voice".to_string())]);
+        assert_eq!(property.value, "tel:+1");
+        assert!(Property::parse("EMAIL;PREF:x").unwrap().is_preferred());
+        assert!(Property::parse("no colon").is_none());
+        assert_eq!(unescape(&escape("a\\b,c;d\ne")), "a\\b,c;d\ne");
+    }
+}
@@ -0,0 +1,396 @@
+mod codec;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::Router;
+use axum::extract::{ConnectInfo, FromRequestParts, Path, Query, Request};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, on, MethodFilter};
+use http::{HeaderMap, HeaderValue, Method, StatusCode};
+use once_cell::sync::Lazy;
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, Kind, MethodDescriptor, ServiceDescriptor};
+use tonic::{Code, Status};
+use tonic::body::BoxBody;
+use tonic::client::{Grpc, GrpcService};
+use tonic::codegen::{Body, StdError};
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpConnectInfo;
+use tonic_types::StatusExt;
+use crate::gateway::codec::RawCodec;
+
+// Requests with a larger body are rejected before being transcoded
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+// Headers forwarded as metadata besides the "x-" ones, so traces continue through the gateway
+const FORWARDED_HEADERS: [&str; 3] = ["traceparent", "tracestate", "baggage"];
+
+pub static DESCRIPTORS: Lazy<DescriptorPool> = Lazy::new(|| {
+    DescriptorPool::decode(include_bytes!(concat!(env!("OUT_DIR"), "/descriptor.bin")).as_ref())
+        .expect("the descriptors generated by build.rs are valid")
+});
+
+// Generated by build.rs from the same google.api.http rules as the routes
+const OPENAPI: &str = include_str!(concat!(env!("OUT_DIR"), "/openapi.json"));
+
+// Renders the OpenAPI document without loading anything besides it
+const DOCS: &str = include_str!("docs.html");
+
+
+// An RPC exposed over HTTP by its google.api.http rule
+#[derive(Debug, Clone)]
+pub struct HttpRoute {
+    pub method: Method,
+    // Path template such as "/v1/agendas/{id}"
+    pub path: String,
+    // Request field filled from the body, "*" for the whole message
+    pub body: Option<String>,
+    pub rpc: MethodDescriptor,
+}
+
+impl HttpRoute {
+    // Path parameters are written "{id}" in the rule and ":id" in axum
+    fn router_path(&self) -> String {
+        self.path.split('/')
+            .map(|segment| match segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) {
+                Some(field) => format!(":{}", field.split('=').next().unwrap_or(field)),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join("/")
+    }
+}
+
+
+pub fn http_routes(service: &ServiceDescriptor) -> Vec<HttpRoute> {
+    let Some(http_rule) = DESCRIPTORS.get_extension_by_name("google.api.http") else {
+        return Vec::new();
+    };
+    let patterns = [("get", Method::GET), ("put", Method::PUT), ("post", Method::POST), ("delete", Method::DELETE), ("patch", Method::PATCH)];
+
+    service.methods()
+        .filter_map(|rpc| {
+            let options = rpc.options();
+            if !options.has_extension(&http_rule) {
+                return None;
+            }
+            let rule = options.get_extension(&http_rule).as_message()?.clone();
+            let (method, path) = patterns.iter().find_map(|(field, method)| {
+                rule.has_field_by_name(field)
+                    .then(|| rule.get_field_by_name(field))
+                    .flatten()
+                    .and_then(|path| path.as_str().map(|path| (method.clone(), path.to_string())))
+            })?;
+            let body = rule.get_field_by_name("body")
+                .and_then(|body| body.as_str().map(|body| body.to_string()))
+                .filter(|body| !body.is_empty());
+            Some(HttpRoute {method, path, body, rpc})
+        })
+        .collect()
+}
+
+
+// Same mapping as grpc-gateway, see https://github.com/googleapis/googleapis/blob/master/google/rpc/code.proto
+pub fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::Cancelled => StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => StatusCode::BAD_REQUEST,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+// The body follows the JSON mapping of google.rpc.Status
+fn error_response(status: Status) -> Response {
+    let mut headers = HeaderMap::new();
+    let retry_after = status.get_details_retry_info().and_then(|info| info.retry_delay);
+    if let Some(retry_after) = retry_after {
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        headers.insert(http::header::RETRY_AFTER, HeaderValue::from(seconds));
+    }
+    let body = serde_json::json!({"code": status.code() as i32, "message": status.message()});
+    (http_status(status.code()), headers, axum::Json(body)).into_response()
+}
+
+
+// Path parameters and query parameters are set on the request fields of the same name, they are
+// given as strings which the proto3 JSON mapping accepts for numbers and timestamps
+fn request_message(route: &HttpRoute, parameters: Vec<(String, String)>, body: &[u8]) -> Result<DynamicMessage, String> {
+    let input = route.rpc.input();
+
+    let mut fields = match (route.body.as_deref(), body.is_empty()) {
+        (Some("*"), false) => serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(body)
+            .map_err(|err| format!("invalid body: {err}"))?,
+        (Some(field), false) => serde_json::Map::from_iter([(
+            field.to_string(),
+            serde_json::from_slice(body).map_err(|err| format!("invalid body: {err}"))?,
+        )]),
+        _ => serde_json::Map::new(),
+    };
+
+    // A PATCH only changes the fields of its body, unless the update mask is given as a parameter
+    let update_mask = input.get_field_by_name("update_mask")
+        .filter(|field| field.kind().as_message().is_some_and(|mask| mask.full_name() == "google.protobuf.FieldMask"));
+    if let (true, Some(field), Some(update_mask)) = (route.method == Method::PATCH, route.body.as_deref(), update_mask) {
+        let paths = fields.get(field)
+            .and_then(|body| body.as_object())
+            .map(|body| body.keys().cloned().collect::<Vec<String>>().join(","));
+        let mask_given = parameters.iter().any(|(name, _)| name == update_mask.name() || name == update_mask.json_name());
+        if let (Some(paths), false) = (paths, mask_given) {
+            fields.insert(update_mask.name().to_string(), serde_json::Value::String(paths));
+        }
+    }
+
+    for (name, value) in parameters {
+        let field = input.get_field_by_name(&name)
+            .or_else(|| input.get_field_by_json_name(&name))
+            .ok_or_else(|| format!("unknown parameter {name}"))?;
+        let value = match field.kind() {
+            Kind::Bool => serde_json::Value::Bool(value.parse().map_err(|_| format!("invalid boolean {name}={value}"))?),
+            _ => serde_json::Value::String(value),
+        };
+        fields.insert(field.name().to_string(), value);
+    }
+
+    DynamicMessage::deserialize(input, serde_json::Value::Object(fields))
+        .map_err(|err| format!("invalid request: {err}"))
+}
+
+fn forwarded_metadata(headers: &HeaderMap) -> MetadataMap {
+    let forwarded = headers.iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-") || FORWARDED_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect::<HeaderMap>();
+    MetadataMap::from_headers(forwarded)
+}
+
+
+// Transcodes the HTTP request and calls the RPC through `service`, so it goes through the same
+// middleware as native gRPC calls
+async fn handle<S>(service: S, route: &HttpRoute, request: Request) -> Result<Response, Status>
+where
+    S: GrpcService<BoxBody>,
+    S::ResponseBody: Body<Data = bytes::Bytes> + Send + 'static,
+    <S::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let (mut parts, body) = request.into_parts();
+    let mut parameters = Path::<HashMap<String, String>>::from_request_parts(&mut parts, &()).await
+        .map(|Path(parameters)| parameters.into_iter().collect::<Vec<(String, String)>>())
+        .unwrap_or_default();
+    let Query(query) = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+        .map_err(|err| Status::new(Code::InvalidArgument, err.body_text()))?;
+    parameters.extend(query);
+    let body = axum::body::to_bytes(body, MAX_BODY_BYTES).await
+        .map_err(|err| Status::new(Code::InvalidArgument, format!("invalid body: {err}")))?;
+    let message = request_message(route, parameters, &body)
+        .map_err(|err| Status::new(Code::InvalidArgument, err))?;
+
+    let mut extensions = tonic::Extensions::new();
+    if let Some(ConnectInfo(addr)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() {
+        extensions.insert(TcpConnectInfo {local_addr: None, remote_addr: Some(*addr)});
+    }
+    let grpc_request = tonic::Request::from_parts(forwarded_metadata(&parts.headers), extensions, message.encode_to_vec());
+    let path = format!("/{}/{}", route.rpc.parent_service().full_name(), route.rpc.name())
+        .parse()
+        .map_err(|err| Status::new(Code::Internal, format!("invalid method path: {err}")))?;
+
+    let mut grpc = Grpc::new(service);
+    grpc.ready().await.map_err(|err| Status::new(Code::Unavailable, err.into().to_string()))?;
+    let response = grpc.unary(grpc_request, path, RawCodec).await?;
+
+    let output = DynamicMessage::decode(route.rpc.output(), response.into_inner().as_slice())
+        .map_err(|err| Status::new(Code::Internal, format!("invalid response: {err}")))?;
+    Ok(axum::Json(output).into_response())
+}
+
+
+// One route for each RPC of the agenda service that has a google.api.http rule, along with its documentation
+pub fn router<S>(service: S) -> Router
+where
+    S: GrpcService<BoxBody> + Clone + Send + Sync + 'static,
+    S::Future: Send,
+    S::ResponseBody: Body<Data = bytes::Bytes> + Send + 'static,
+    <S::ResponseBody as Body>::Error: Into<StdError> + Send,
+{
+    let routes = DESCRIPTORS.get_service_by_name("agenda.v1.AgendaService")
+        .map(|agenda_service| http_routes(&agenda_service))
+        .unwrap_or_default();
+
+    let documentation = Router::new()
+        .route("/openapi.json", get(|| async { ([(http::header::CONTENT_TYPE, "application/json")], OPENAPI) }))
+        .route("/docs", get(|| async { Html(DOCS) }));
+
+    routes.into_iter().fold(documentation, |router, route| {
+        let Ok(filter) = MethodFilter::try_from(route.method.clone()) else {
+            return router;
+        };
+        let path = route.router_path();
+        let route = Arc::new(route);
+        let service = service.clone();
+        router.route(&path, on(filter, move |request: Request| async move {
+            handle(service, &route, request).await.unwrap_or_else(error_response)
+        }))
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tower::ServiceExt;
+    use crate::agenda::agenda_service_server::AgendaServiceServer;
+    use crate::service::CustomAgendaService;
+    use super::*;
+
+    fn agenda_routes() -> Vec<HttpRoute> {
+        http_routes(&DESCRIPTORS.get_service_by_name("agenda.v1.AgendaService").unwrap())
+    }
+
+    async fn call(router: &Router, method: Method, uri: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), MAX_BODY_BYTES).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+    }
+
+    #[tokio::test]
+    async fn test_http_routes() {
+        let routes = agenda_routes();
+        let route = |name: &str| routes.iter().find(|route| route.rpc.name() == name).unwrap();
+
+        assert_eq!(routes.len(), 5);
+        assert_eq!(route("GetAgenda").method, Method::GET);
+        assert_eq!(route("GetAgenda").router_path(), "/v1/agendas/:id");
+        assert_eq!(route("CreateAgenda").body.as_deref(), Some("agenda"));
+        assert_eq!(route("UpdateAgenda").method, Method::PATCH);
+        assert!(routes.iter().all(|route| route.rpc.name() != "Ping"));
+    }
+
+    #[tokio::test]
+    async fn test_request_message() {
+        let routes = agenda_routes();
+        let update = routes.iter().find(|route| route.rpc.name() == "UpdateAgenda").unwrap();
+
+        let message = request_message(update, vec![("id".into(), "7".into())], br#"{"name": "n", "email": "e", "phone": "p"}"#).unwrap();
+        let message = message.transcode_to::<crate::agenda::UpdateAgendaRequest>().unwrap();
+        assert_eq!(message.id, 7);
+        assert_eq!(message.agenda.unwrap().name, "n");
+        assert_eq!(message.update_mask.unwrap().paths, vec!["email", "name", "phone"]);
+
+        let message = request_message(update, vec![("id".into(), "7".into()), ("updateMask".into(), "phone".into())], br#"{"email": "e"}"#).unwrap();
+        let message = message.transcode_to::<crate::agenda::UpdateAgendaRequest>().unwrap();
+        assert_eq!(message.update_mask.unwrap().paths, vec!["phone"]);
+
+        assert_eq!(request_message(update, vec![("unknown".into(), "1".into())], b"").unwrap_err(), "unknown parameter unknown");
+        assert!(request_message(update, vec![], b"not json").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_status() {
+        assert_eq!(http_status(Code::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(http_status(Code::AlreadyExists), StatusCode::CONFLICT);
+        assert_eq!(http_status(Code::Unavailable), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(http_status(Code::Internal), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_openapi() {
+        let document: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+        let paths = document["paths"].as_object().unwrap();
+
+        // Every route served by the gateway is documented, and nothing else
+        let routes = agenda_routes();
+        assert_eq!(paths.values().map(|methods| methods.as_object().unwrap().len()).sum::<usize>(), routes.len());
+        for route in &routes {
+            let operation = &document["paths"][&route.path][route.method.as_str().to_lowercase()];
+            assert_eq!(operation["operationId"], route.rpc.name());
+        }
+
+        let get_agenda = &document["paths"]["/v1/agendas/{id}"]["get"];
+        assert_eq!(get_agenda["parameters"][0]["in"], "path");
+        assert_eq!(get_agenda["parameters"][1]["name"], "asOf");
+        assert_eq!(get_agenda["parameters"][1]["schema"]["format"], "date-time");
+        let update_mask = document["paths"]["/v1/agendas/{id}"]["patch"]["parameters"].as_array().unwrap().iter()
+            .find(|parameter| parameter["name"] == "updateMask")
+            .unwrap();
+        assert_eq!(update_mask["in"], "query");
+        assert_eq!(update_mask["schema"]["type"], "string");
+        assert_eq!(document["paths"]["/v1/agendas"]["post"]["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/agenda.v1.Agenda");
+        assert_eq!(document["components"]["schemas"]["agenda.v1.Agenda"]["properties"]["id"]["type"], "string");
+
+        // The documented errors follow the mapping of http_status
+        let codes = [
+            (Code::Cancelled, "CANCELLED"), (Code::Unknown, "UNKNOWN"), (Code::InvalidArgument, "INVALID_ARGUMENT"),
+            (Code::DeadlineExceeded, "DEADLINE_EXCEEDED"), (Code::NotFound, "NOT_FOUND"), (Code::AlreadyExists, "ALREADY_EXISTS"),
+            (Code::PermissionDenied, "PERMISSION_DENIED"), (Code::ResourceExhausted, "RESOURCE_EXHAUSTED"),
+            (Code::FailedPrecondition, "FAILED_PRECONDITION"), (Code::Aborted, "ABORTED"), (Code::OutOfRange, "OUT_OF_RANGE"),
+            (Code::Unimplemented, "UNIMPLEMENTED"), (Code::Internal, "INTERNAL"), (Code::Unavailable, "UNAVAILABLE"),
+            (Code::DataLoss, "DATA_LOSS"), (Code::Unauthenticated, "UNAUTHENTICATED"),
+        ];
+        for (code, name) in codes {
+            let description = document["components"]["responses"][http_status(code).as_str()]["description"].as_str().unwrap();
+            assert!(description.contains(name), "{name} is not documented as {}", http_status(code));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let service = Arc::new(CustomAgendaService::new().await.unwrap());
+        let router = router(AgendaServiceServer::from_arc(service));
+        let name = format!("gateway-{}", uuid::Uuid::new_v4());
+
+        let (status, created) = call(&router, Method::POST, "/v1/agendas", &format!(r#"{{"name": "{name}", "email": "a@b.c", "phone": "1"}}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        let id = created["agenda"]["id"].as_str().unwrap().to_string();
+
+        let (status, duplicate) = call(&router, Method::POST, "/v1/agendas", &format!(r#"{{"name": "{name}", "email": "a@b.c", "phone": "1"}}"#)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(duplicate["code"], Code::AlreadyExists as i32);
+
+        let (status, updated) = call(&router, Method::PATCH, &format!("/v1/agendas/{id}"), &format!(r#"{{"name": "{name}", "email": "new@b.c", "phone": "2"}}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["agenda"]["email"], "new@b.c");
+
+        let (status, fetched) = call(&router, Method::GET, &format!("/v1/agendas/{id}"), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fetched["agenda"]["phone"], "2");
+
+        // A partial body leaves the other fields as they are
+        let (status, patched) = call(&router, Method::PATCH, &format!("/v1/agendas/{id}"), r#"{"email": "partial@b.c"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(patched["agenda"]["name"], name.as_str());
+        assert_eq!(patched["agenda"]["email"], "partial@b.c");
+        assert_eq!(patched["agenda"]["phone"], "2");
+        let (status, _) = call(&router, Method::PATCH, &format!("/v1/agendas/{id}"), r#"{"email": "x", "unknown": "y"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, page) = call(&router, Method::GET, "/v1/agendas?page=1&items=1", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(page["agendas"].as_array().map(|agendas| agendas.len()), Some(1));
+
+        assert_eq!(call(&router, Method::DELETE, &format!("/v1/agendas/{id}"), "").await.0, StatusCode::OK);
+        assert_eq!(call(&router, Method::GET, &format!("/v1/agendas/{id}"), "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(call(&router, Method::GET, "/v1/agendas/not-a-number", "").await.0, StatusCode::BAD_REQUEST);
+
+        let (status, document) = call(&router, Method::GET, "/openapi.json", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(document["openapi"], "3.0.3");
+        assert_eq!(call(&router, Method::GET, "/docs", "").await.0, StatusCode::OK);
+    }
+}
@@ -1,32 +1,189 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use clap::Parser;
 use tonic::transport::Server;
-use tokio::{time::Duration, time};
+use tonic_health::ServingStatus;
+use tonic_web::GrpcWebLayer;
+use tokio::sync::watch;
+use tokio::time;
+use tower::ServiceBuilder;
+use crate::admin::admin_service_server::AdminServiceServer;
+use crate::command::{Cli, Command};
 use crate::agenda::agenda_service_server::AgendaServiceServer;
+use crate::middleware::auth::ApiKeyAuthLayer;
+use crate::middleware::cors::cors_layer_from_env;
+use crate::middleware::metrics::MetricsLayer;
+use crate::middleware::propagation::TracePropagationLayer;
+use crate::middleware::limits::{DeadlineLayer, LoadShedLayer, RequestLimits};
+use crate::middleware::ratelimit::{RateLimitBy, RateLimitLayer, RateLimits};
+use crate::middleware::routing::ReadRoutingLayer;
 use crate::otel::{init_tracer_and_logger, stop_tracer_and_logger};
+use crate::shutdown::{drain_deadline, grace_period, report_step, shutdown_signal, CLOSE_POOL_TIMEOUT};
 
+mod audit;
+mod auth;
+mod command;
 mod service;
 mod model;
 mod database;
+mod format;
+mod gateway;
+mod middleware;
 mod otel;
+mod shutdown;
 
 pub mod agenda {
     tonic::include_proto!("agenda.v1");
 }
 
+pub mod admin {
+    tonic::include_proto!("admin.v1");
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::]:50051".parse()?;
-    let agenda_service = service::CustomAgendaService::new().await;
-    
-    let _ = init_tracer_and_logger()?;
-
-    Server::builder()
-        .add_service(AgendaServiceServer::new(agenda_service))
-        .serve_with_shutdown(addr, async {
-            tokio::signal::ctrl_c().await.expect("failed to install CTRL+C signal handler");
-            stop_tracer_and_logger();
-            time::sleep(Duration::from_secs(1)).await;
-        })
-        .await?;
-
-    Ok(())
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve().await,
+        Command::Migrate {status} => command::migrate(status).await,
+        Command::CheckConfig => command::check_config().await,
+        Command::Export {file, page_size} => command::export_agendas(&file, page_size).await,
+        Command::Import {file} => command::import_agendas(&file).await,
+        Command::CreateApiKey {name, scopes} => command::create_api_key(name, scopes).await,
+        Command::Version => {
+            println!("{}", command::version());
+            Ok(())
+        }
+    }
+}
+
+// Addresses of the agenda service, the admin service and the REST gateway
+fn server_addrs() -> Result<(SocketAddr, SocketAddr, SocketAddr), String> {
+    let parse = |name: &str, default: &str| std::env::var(name).unwrap_or(default.to_string())
+        .parse::<SocketAddr>()
+        .map_err(|err| format!("{name}: {err}"));
+    // The admin service is served on its own address, only reachable from the host unless configured
+    Ok(("[::]:50051".parse().map_err(|err| format!("{err}"))?, parse("ADMIN_ADDR", "127.0.0.1:50052")?, parse("GATEWAY_ADDR", "[::]:8080")?))
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    let (addr, admin_addr, gateway_addr) = server_addrs()?;
+    // Telemetry goes first so that the metrics registered by the database layer are exported
+    init_tracer_and_logger()?;
+
+    let agenda_service = Arc::new(service::CustomAgendaService::new().await?);
+
+    // Kept to close the pool once the servers are drained
+    let database = Arc::clone(&agenda_service.database);
+    let admin_service = service::admin::CustomAdminService::new(Arc::clone(&database));
+
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter.set_serving::<AgendaServiceServer<service::CustomAgendaService>>().await;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut admin_shutdown_rx = shutdown_rx.clone();
+    let mut agenda_shutdown_rx = shutdown_rx.clone();
+    let mut gateway_shutdown_rx = shutdown_rx;
+
+    let auth_layer = ApiKeyAuthLayer::from_env(Arc::clone(&database))?;
+    let admin_server = Server::builder()
+        .layer(MetricsLayer)
+        .layer(TracePropagationLayer)
+        .layer(auth_layer.admin())
+        .add_service(AdminServiceServer::new(admin_service))
+        .serve_with_shutdown(admin_addr, async move {
+            let _ = admin_shutdown_rx.wait_for(|stop| *stop).await;
+        });
+
+    let request_limits = RequestLimits::from_env()?;
+    // HTTP/1.1 lets browsers call the service through gRPC-Web, native gRPC is still served on HTTP/2
+    let mut agenda_builder = Server::builder().accept_http1(true);
+    // Requests over the per connection limit wait for a slot, over the global limit they are rejected
+    if let Some(max) = request_limits.max_concurrent_per_connection {
+        agenda_builder = agenda_builder.concurrency_limit_per_connection(max);
+    }
+    // Shared by the gRPC server and the REST gateway, so both are limited, authenticated and measured alike.
+    // Clients are limited by address before their key is verified, and by key once it is, each with its own limits.
+    let peer_rate_limits = RateLimits::peer_from_env()?;
+    let rate_limits = RateLimits::from_env()?;
+    let agenda_layers = ServiceBuilder::new()
+        .layer(MetricsLayer)
+        .layer(TracePropagationLayer)
+        .layer(RateLimitLayer::new(peer_rate_limits, RateLimitBy::Peer))
+        .layer(LoadShedLayer::new(request_limits.max_concurrent))
+        .layer(DeadlineLayer::new(request_limits))
+        .layer(auth_layer)
+        .layer(RateLimitLayer::new(rate_limits, RateLimitBy::Client))
+        .layer(ReadRoutingLayer);
+    let agenda_server = agenda_builder
+        .layer(cors_layer_from_env()?)
+        .layer(GrpcWebLayer::new())
+        .layer(agenda_layers.clone())
+        .add_service(health_service)
+        .add_service(AgendaServiceServer::from_arc(Arc::clone(&agenda_service)))
+        .serve_with_shutdown(addr, async move {
+            let _ = agenda_shutdown_rx.wait_for(|stop| *stop).await;
+        });
+
+    let gateway_router = gateway::router(agenda_layers.service(AgendaServiceServer::from_arc(agenda_service)));
+    let gateway_server = axum::serve(
+        tokio::net::TcpListener::bind(gateway_addr).await?,
+        gateway_router.into_make_service_with_connect_info::<SocketAddr>(),
+    ).with_graceful_shutdown(async move {
+        let _ = gateway_shutdown_rx.wait_for(|stop| *stop).await;
+    });
+
+    // Boxed so it can be dropped before the pool is closed
+    let mut servers = Box::pin(async {
+        tokio::try_join!(
+            async { agenda_server.await.map_err(|err| format!("agenda server: {err}")) },
+            async { admin_server.await.map_err(|err| format!("admin server: {err}")) },
+            async { gateway_server.await.map_err(|err| format!("gateway: {err}")) },
+        )
+    });
+
+    // A server failing stops the others, the pool is closed and the telemetry flushed all the same
+    let stopped = tokio::select! {
+        result = &mut servers => Some(result),
+        signal = shutdown_signal() => {
+            tracing::info!("Received {}, shutting down", signal);
+            None
+        },
+    };
+
+    let result = match stopped {
+        Some(result) => result.map(|_| ()),
+        None => {
+            report_step("mark not serving", async {
+                health_reporter.set_not_serving::<AgendaServiceServer<service::CustomAgendaService>>().await;
+                health_reporter.set_service_status("", ServingStatus::NotServing).await;
+                Ok::<(), Infallible>(())
+            }).await;
+            time::sleep(grace_period()).await;
+
+            // The servers stop accepting connections and wait for the in-flight requests to complete
+            let _ = shutdown_tx.send(true);
+            report_step("drain requests", async {
+                match time::timeout(drain_deadline(), &mut servers).await {
+                    Ok(result) => result.map(|_| ()),
+                    Err(_) => Err("deadline exceeded, the remaining requests were cancelled".to_string()),
+                }
+            }).await;
+            Ok(())
+        },
+    };
+    if let Err(err) = &result {
+        tracing::error!("Server stopped: {}", err);
+    }
+    // The requests left after the drain deadline are cancelled, a pool close waits for their connections
+    drop(servers);
+
+    report_step("close database pool", async {
+        time::timeout(CLOSE_POOL_TIMEOUT, database.get_db_handler().close()).await
+            .map_err(|_| "timed out waiting for the connections in use")
+    }).await;
+
+    stop_tracer_and_logger().await;
+
+    Ok(result?)
 }
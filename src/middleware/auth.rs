@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+use crate::auth::{parse_api_key, required_scope, verify_secret, Principal};
+use crate::database::database_object::DBLayers;
+use crate::database::error::DatabaseError;
+use crate::middleware::{grpc_service_and_method, status_response, BoxFuture};
+use crate::middleware::ratelimit::{client_key, RateLimitBy, API_KEY_METADATA_KEY, EXEMPT_SERVICES};
+
+// Cached keys are forgotten once this many are tracked
+const MAX_CACHED_KEYS: usize = 10_000;
+// After a failed verification, uncached secrets for the same key id from the same peer are rejected
+// for this long, so a client sending random secrets cannot make the server run argon2 on every request.
+// Other peers are not affected, key ids are public and must not let anyone lock the owner out.
+const FAILED_KEY_BACKOFF: Duration = Duration::from_secs(1);
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiKeyAuth {
+    // The x-api-key header is ignored
+    Disabled,
+    // Requests without a key are anonymous, a key that is sent must be valid
+    Optional,
+    Required,
+}
+
+impl ApiKeyAuth {
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("API_KEY_AUTH").unwrap_or("disabled".to_string()).as_str() {
+            "disabled" => Ok(ApiKeyAuth::Disabled),
+            "optional" => Ok(ApiKeyAuth::Optional),
+            "required" => Ok(ApiKeyAuth::Required),
+            mode => Err(format!("API_KEY_AUTH: unknown mode {mode}, expected disabled, optional or required")),
+        }
+    }
+}
+
+
+// A verified key, the digest of its secret lets later requests skip the argon2 verification
+#[derive(Debug)]
+struct CachedKey {
+    digest: u64,
+    principal: Principal,
+    cached_until: Instant,
+}
+
+#[derive(Debug)]
+struct ApiKeyVerifier {
+    database: Arc<DBLayers>,
+    cache_ttl: Duration,
+    cache: Mutex<HashMap<i64, CachedKey>>,
+    // Peers and key ids, unknown ones included, whose last verification failed, until when they are rejected
+    failures: Mutex<HashMap<(String, i64), Instant>>,
+    // Keyed per process, so digests cannot be precomputed
+    hasher: RandomState,
+}
+
+impl ApiKeyVerifier {
+    fn cached(&self, id: i64, digest: u64) -> Option<Principal> {
+        let cache = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        cache.get(&id)
+            .filter(|cached| cached.digest == digest && cached.cached_until > Instant::now())
+            .map(|cached| cached.principal.clone())
+    }
+
+    fn insert(&self, id: i64, digest: u64, principal: Principal, valid_for: Duration) {
+        let mut cache = self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if cache.len() > MAX_CACHED_KEYS {
+            let now = Instant::now();
+            cache.retain(|_, cached| cached.cached_until > now);
+        }
+        cache.insert(id, CachedKey {digest, principal, cached_until: Instant::now() + valid_for});
+    }
+
+    fn backing_off(&self, peer: &str, id: i64) -> bool {
+        let failures = self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        failures.get(&(peer.to_string(), id)).is_some_and(|until| *until > Instant::now())
+    }
+
+    fn record_failure(&self, peer: &str, id: i64) {
+        let mut failures = self.failures.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let now = Instant::now();
+        if failures.len() > MAX_CACHED_KEYS {
+            failures.retain(|_, until| *until > now);
+        }
+        failures.insert((peer.to_string(), id), now + FAILED_KEY_BACKOFF);
+    }
+
+    // Revoked keys keep working until their cache entry expires
+    async fn verify(&self, peer: &str, key: &str) -> Result<Principal, Status> {
+        let invalid = || Status::new(Code::Unauthenticated, "invalid API key");
+        let (id, secret) = parse_api_key(key).ok_or_else(invalid)?;
+        let digest = self.hasher.hash_one(secret);
+        if let Some(principal) = self.cached(id, digest) {
+            return Ok(principal);
+        }
+        if self.backing_off(peer, id) {
+            return Err(invalid());
+        }
+
+        let api_key = match self.database.get_db_handler().retrieve_api_key(id).await {
+            Ok(api_key) => api_key,
+            Err(DatabaseError::NotFoundError {..}) => {
+                self.record_failure(peer, id);
+                return Err(invalid());
+            },
+            Err(err) => return Err(err.into()),
+        };
+
+        // Argon2 is deliberately slow, it must not block the runtime
+        let (secret, secret_hash) = (secret.to_string(), api_key.secret_hash.clone());
+        let verified = tokio::task::spawn_blocking(move || verify_secret(&secret, &secret_hash))
+            .await
+            .map_err(|err| Status::new(Code::Internal, err.to_string()))?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as i64;
+        if !verified || !api_key.is_active(now) {
+            self.record_failure(peer, id);
+            return Err(invalid());
+        }
+
+        if let Err(err) = self.database.get_db_handler().touch_api_key(id).await {
+            tracing::warn!("Could not record the use of API key {}: {}", id, err);
+        }
+
+        let principal = Principal {key_id: api_key.id, name: api_key.name, scopes: api_key.scopes};
+        // An expiring key is not cached past its expiry
+        let valid_for = api_key.expires_at
+            .map(|expires_at| Duration::from_micros((expires_at - now) as u64).min(self.cache_ttl))
+            .unwrap_or(self.cache_ttl);
+        self.insert(id, digest, principal.clone(), valid_for);
+        Ok(principal)
+    }
+}
+
+
+// Verifies the x-api-key header and adds the Principal to the request extensions, keys lacking
+// the scope of the method get PermissionDenied
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuthLayer {
+    mode: ApiKeyAuth,
+    verifier: Option<Arc<ApiKeyVerifier>>,
+}
+
+impl ApiKeyAuthLayer {
+    pub fn new(mode: ApiKeyAuth, database: Arc<DBLayers>, cache_ttl: Duration) -> Self {
+        ApiKeyAuthLayer {
+            mode,
+            verifier: (mode != ApiKeyAuth::Disabled).then(|| Arc::new(ApiKeyVerifier {
+                database,
+                cache_ttl,
+                cache: Mutex::new(HashMap::new()),
+                failures: Mutex::new(HashMap::new()),
+                hasher: RandomState::new(),
+            })),
+        }
+    }
+
+    // The admin service can create keys of any scope, so once keys are enabled it never allows
+    // anonymous calls. The verified keys are shared with this layer.
+    pub fn admin(&self) -> Self {
+        let mode = match self.mode {
+            ApiKeyAuth::Disabled => ApiKeyAuth::Disabled,
+            _ => ApiKeyAuth::Required,
+        };
+        ApiKeyAuthLayer {mode, verifier: self.verifier.clone()}
+    }
+
+    pub fn from_env(database: Arc<DBLayers>) -> Result<Self, String> {
+        let cache_ttl = std::env::var("API_KEY_CACHE_TTL_SECS").ok()
+            .map(|ttl| ttl.parse::<u64>().map_err(|err| format!("API_KEY_CACHE_TTL_SECS: {err}")))
+            .transpose()?
+            .unwrap_or(10);
+        Ok(Self::new(ApiKeyAuth::from_env()?, database, Duration::from_secs(cache_ttl)))
+    }
+}
+
+impl<S> Layer<S> for ApiKeyAuthLayer {
+    type Service = ApiKeyAuthService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ApiKeyAuthService { inner, mode: self.mode, verifier: self.verifier.clone() }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ApiKeyAuthService<S> {
+    inner: S,
+    mode: ApiKeyAuth,
+    verifier: Option<Arc<ApiKeyVerifier>>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for ApiKeyAuthService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let (service, method) = grpc_service_and_method(request.uri().path());
+        let Some(verifier) = self.verifier.clone().filter(|_| !EXEMPT_SERVICES.contains(&service)) else {
+            return Box::pin(self.inner.call(request));
+        };
+        let scope = required_scope(service, method);
+        let mode = self.mode;
+        let peer = client_key(&request, RateLimitBy::Peer).unwrap_or_default();
+        let api_key = request.headers()
+            .get(API_KEY_METADATA_KEY)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string());
+
+        // The ready service is taken and replaced by a clone, as the call happens after an await
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let principal = match (api_key, mode) {
+                (Some(api_key), _) => verifier.verify(&peer, &api_key).await,
+                (None, ApiKeyAuth::Required) => Err(Status::new(Code::Unauthenticated, "missing API key")),
+                (None, _) => return inner.call(request).await,
+            };
+
+            match principal {
+                Ok(principal) if principal.has_scope(scope) => {
+                    request.extensions_mut().insert(principal);
+                    inner.call(request).await
+                },
+                Ok(_) => Ok(status_response(&Status::new(Code::PermissionDenied, format!("the API key lacks the {scope} scope")))),
+                Err(status) => Ok(status_response(&status)),
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use tonic::transport::server::TcpConnectInfo;
+    use crate::auth::{format_api_key, generate_secret, SCOPE_ADMIN, SCOPE_READ, SCOPE_WRITE};
+    use crate::middleware::metrics::response_code;
+    use crate::model::ApiKeyModel;
+    use super::*;
+
+    async fn database() -> Arc<DBLayers> {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let database = DBLayers::new_db_handler().await.unwrap();
+        database.get_db_handler().init_database().await.unwrap();
+        Arc::new(database)
+    }
+
+    fn echo_service(layer: &ApiKeyAuthLayer) -> impl Service<http::Request<()>, Response = http::Response<tonic::body::BoxBody>, Error = Infallible> {
+        layer.layer(tower::service_fn(|request: http::Request<()>| async move {
+            // The principal is echoed so tests can check it reached the handler
+            let mut response = http::Response::new(tonic::body::empty_body());
+            if let Some(principal) = request.extensions().get::<Principal>() {
+                response.headers_mut().insert("x-principal", principal.actor().parse().unwrap());
+            }
+            Ok::<_, Infallible>(response)
+        }))
+    }
+
+    fn request(method: &str, api_key: Option<&str>) -> http::Request<()> {
+        let mut request = http::Request::builder().uri(format!("/agenda.v1.AgendaService/{method}"));
+        if let Some(api_key) = api_key {
+            request = request.header(API_KEY_METADATA_KEY, api_key);
+        }
+        request.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth_from_env() {
+        assert_eq!(ApiKeyAuth::from_env(), Ok(ApiKeyAuth::Disabled));
+
+        std::env::set_var("API_KEY_AUTH", "sometimes");
+        assert!(ApiKeyAuth::from_env().is_err());
+        std::env::remove_var("API_KEY_AUTH");
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth_service() {
+        let database = database().await;
+        let (secret, hash) = generate_secret().unwrap();
+        let created = database.get_db_handler()
+            .create_api_key(ApiKeyModel::new("reader".into(), hash, vec![SCOPE_READ.into()], None))
+            .await
+            .unwrap();
+        let api_key = format_api_key(created.id, &secret);
+        let mut service = echo_service(&ApiKeyAuthLayer::new(ApiKeyAuth::Required, database.clone(), Duration::from_secs(60)));
+
+        let response = service.call(request("GetAgenda", Some(&api_key))).await;
+        assert_eq!(response_code(&response), Code::Ok);
+        assert_eq!(response.unwrap().headers().get("x-principal").unwrap(), &format!("key:{}", created.id));
+        assert!(database.get_db_handler().retrieve_api_key(created.id).await.unwrap().last_used_at.is_some());
+
+        assert_eq!(response_code(&service.call(request("DeleteAgenda", Some(&api_key))).await), Code::PermissionDenied);
+        assert_eq!(response_code(&service.call(request("GetAgenda", None)).await), Code::Unauthenticated);
+        assert_eq!(response_code(&service.call(request("GetAgenda", Some(&format_api_key(created.id, "wrong")))).await), Code::Unauthenticated);
+
+        // Positive lookups are cached, a revoked key is rejected once it is verified again
+        database.get_db_handler().revoke_api_key(created.id).await.unwrap();
+        assert_eq!(response_code(&service.call(request("GetAgenda", Some(&api_key))).await), Code::Ok);
+        let mut uncached = echo_service(&ApiKeyAuthLayer::new(ApiKeyAuth::Required, database, Duration::from_secs(60)));
+        assert_eq!(response_code(&uncached.call(request("GetAgenda", Some(&api_key))).await), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_failure_backoff() {
+        let database = database().await;
+        let (secret, hash) = generate_secret().unwrap();
+        let created = database.get_db_handler()
+            .create_api_key(ApiKeyModel::new("reader".into(), hash, vec![SCOPE_READ.into()], None))
+            .await
+            .unwrap();
+        let mut service = echo_service(&ApiKeyAuthLayer::new(ApiKeyAuth::Required, database, Duration::from_secs(60)));
+        let from_peer = |peer: &str, api_key: &str| {
+            let mut request = request("GetAgenda", Some(api_key));
+            request.extensions_mut().insert(TcpConnectInfo {local_addr: None, remote_addr: Some(peer.parse().unwrap())});
+            request
+        };
+        let (wrong, right) = (format_api_key(created.id, "wrong"), format_api_key(created.id, &secret));
+
+        // A wrong secret makes the key id back off for the failing peer only, the owner still gets in
+        assert_eq!(response_code(&service.call(from_peer("10.0.0.1:4000", &wrong)).await), Code::Unauthenticated);
+        assert_eq!(response_code(&service.call(from_peer("10.0.0.2:4000", &right)).await), Code::Ok);
+        assert_eq!(response_code(&service.call(from_peer("10.0.0.1:4000", &wrong)).await), Code::Unauthenticated);
+        assert_eq!(response_code(&service.call(from_peer("10.0.0.3:4000", &right)).await), Code::Ok);
+
+        // Once verified the key is cached, it is accepted from the failing peer as well
+        assert_eq!(response_code(&service.call(from_peer("10.0.0.1:4000", &right)).await), Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth_optional() {
+        let mut service = echo_service(&ApiKeyAuthLayer::new(ApiKeyAuth::Optional, database().await, Duration::from_secs(60)));
+
+        assert_eq!(response_code(&service.call(request("GetAgenda", None)).await), Code::Ok);
+        assert_eq!(response_code(&service.call(request("GetAgenda", Some("agk_0_nope"))).await), Code::Unauthenticated);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_auth_admin() {
+        let database = database().await;
+        let (secret, hash) = generate_secret().unwrap();
+        let created = database.get_db_handler()
+            .create_api_key(ApiKeyModel::new("writer".into(), hash, vec![SCOPE_READ.into(), SCOPE_WRITE.into()], None))
+            .await
+            .unwrap();
+        let api_key = format_api_key(created.id, &secret);
+        let admin_request = |api_key: Option<&str>| {
+            let mut request = http::Request::builder().uri("/admin.v1.AdminService/CreateApiKey");
+            if let Some(api_key) = api_key {
+                request = request.header(API_KEY_METADATA_KEY, api_key);
+            }
+            request.body(()).unwrap()
+        };
+
+        // Anonymous agenda calls are allowed in optional mode, anonymous admin calls are not
+        let layer = ApiKeyAuthLayer::new(ApiKeyAuth::Optional, database.clone(), Duration::from_secs(60));
+        let mut admin = echo_service(&layer.admin());
+        assert_eq!(response_code(&admin.call(admin_request(None)).await), Code::Unauthenticated);
+        assert_eq!(response_code(&admin.call(admin_request(Some(&api_key))).await), Code::PermissionDenied);
+
+        let (secret, hash) = generate_secret().unwrap();
+        let created = database.get_db_handler()
+            .create_api_key(ApiKeyModel::new("admin".into(), hash, vec![SCOPE_ADMIN.into()], None))
+            .await
+            .unwrap();
+        assert_eq!(response_code(&admin.call(admin_request(Some(&format_api_key(created.id, &secret)))).await), Code::Ok);
+
+        let disabled = ApiKeyAuthLayer::new(ApiKeyAuth::Disabled, database, Duration::from_secs(60));
+        assert_eq!(response_code(&echo_service(&disabled.admin()).call(admin_request(None)).await), Code::Ok);
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+use http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use crate::audit::ACTOR_METADATA_KEY;
+use crate::middleware::ratelimit::API_KEY_METADATA_KEY;
+use crate::middleware::routing::READ_FROM_PRIMARY_METADATA_KEY;
+
+// Request headers sent by gRPC-Web clients, along with the metadata this server reads
+const DEFAULT_ALLOWED_HEADERS: [&str; 9] = [
+    "content-type", "x-grpc-web", "x-user-agent", "grpc-timeout", "traceparent", "tracestate",
+    API_KEY_METADATA_KEY, ACTOR_METADATA_KEY, READ_FROM_PRIMARY_METADATA_KEY,
+];
+
+// gRPC-Web responses carry the status in headers when there is no body, the browser must see them
+const EXPOSED_HEADERS: [&str; 3] = ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|item| item.trim()).filter(|item| !item.is_empty())
+}
+
+// Browsers are only allowed cross-origin calls from CORS_ALLOWED_ORIGINS, none by default
+pub fn cors_layer_from_env() -> Result<CorsLayer, String> {
+    let origins = std::env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
+    let allow_credentials = std::env::var("CORS_ALLOW_CREDENTIALS").ok()
+        .map(|value| value.parse::<bool>().map_err(|err| format!("CORS_ALLOW_CREDENTIALS: {err}")))
+        .transpose()?
+        .unwrap_or(false);
+    let max_age = std::env::var("CORS_MAX_AGE_SECS").ok()
+        .map(|value| value.parse::<u64>().map_err(|err| format!("CORS_MAX_AGE_SECS: {err}")))
+        .transpose()?
+        .unwrap_or(86400);
+
+    let allow_origin = match origins.trim() {
+        // Browsers reject credentials for any origin, so it is refused here rather than failing in the browser
+        "*" if allow_credentials => return Err("CORS_ALLOW_CREDENTIALS cannot be used with any origin in CORS_ALLOWED_ORIGINS".to_string()),
+        "*" => AllowOrigin::any(),
+        // Any origin cannot be mixed with others, tower-http panics on a wildcard in a list
+        origins if split_list(origins).any(|origin| origin == "*") => return Err("CORS_ALLOWED_ORIGINS: * must be the only origin".to_string()),
+        origins => AllowOrigin::list(split_list(origins)
+            .map(|origin| HeaderValue::from_str(origin).map_err(|err| format!("CORS_ALLOWED_ORIGINS: invalid origin {origin}: {err}")))
+            .collect::<Result<Vec<HeaderValue>, String>>()?),
+    };
+
+    let extra_headers = std::env::var("CORS_ALLOWED_HEADERS").unwrap_or_default();
+    let allowed_headers = DEFAULT_ALLOWED_HEADERS.into_iter()
+        .chain(split_list(&extra_headers))
+        .map(|header| HeaderName::from_bytes(header.as_bytes()).map_err(|err| format!("CORS_ALLOWED_HEADERS: invalid header {header}: {err}")))
+        .collect::<Result<Vec<HeaderName>, String>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST])
+        .allow_headers(allowed_headers)
+        .expose_headers(EXPOSED_HEADERS.map(HeaderName::from_static))
+        .allow_credentials(allow_credentials)
+        .max_age(Duration::from_secs(max_age)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use tower::{Layer, Service};
+    use super::*;
+
+    async fn preflight(layer: CorsLayer, origin: &str) -> http::Response<tonic::body::BoxBody> {
+        let mut service = layer.layer(tower::service_fn(|_request: http::Request<()>| async {
+            Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body()))
+        }));
+        let request = http::Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/agenda.v1.AgendaService/GetAgenda")
+            .header(http::header::ORIGIN, origin)
+            .header(http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(http::header::ACCESS_CONTROL_REQUEST_HEADERS, "x-grpc-web,x-api-key")
+            .body(())
+            .unwrap();
+        service.call(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cors_allowed_origins() {
+        std::env::set_var("CORS_ALLOWED_ORIGINS", "https://admin.example.com, https://other.example.com");
+        std::env::set_var("CORS_ALLOW_CREDENTIALS", "true");
+        let layer = cors_layer_from_env().unwrap();
+        std::env::remove_var("CORS_ALLOWED_ORIGINS");
+        std::env::remove_var("CORS_ALLOW_CREDENTIALS");
+
+        let allowed = preflight(layer.clone(), "https://admin.example.com").await;
+        assert_eq!(allowed.headers().get(http::header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "https://admin.example.com");
+        assert_eq!(allowed.headers().get(http::header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert!(allowed.headers().get(http::header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap().to_str().unwrap().contains("x-api-key"));
+
+        let denied = preflight(layer, "https://evil.example.com").await;
+        assert!(denied.headers().get(http::header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_invalid_config() {
+        std::env::set_var("CORS_ALLOWED_ORIGINS", "*");
+        std::env::set_var("CORS_ALLOW_CREDENTIALS", "true");
+        assert!(cors_layer_from_env().is_err());
+
+        std::env::set_var("CORS_ALLOW_CREDENTIALS", "yes");
+        assert!(cors_layer_from_env().is_err());
+
+        std::env::remove_var("CORS_ALLOW_CREDENTIALS");
+        std::env::set_var("CORS_ALLOWED_ORIGINS", "*,https://a.example");
+        assert_eq!(cors_layer_from_env().err(), Some("CORS_ALLOWED_ORIGINS: * must be the only origin".to_string()));
+
+        std::env::remove_var("CORS_ALLOWED_ORIGINS");
+        std::env::remove_var("CORS_ALLOW_CREDENTIALS");
+        assert!(cors_layer_from_env().is_ok());
+    }
+}
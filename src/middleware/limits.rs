@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::{Code, Status};
+use tower::{Layer, Service};
+use crate::database::deadline::with_deadline;
+use crate::middleware::{grpc_service_and_method, status_response, BoxFuture};
+use crate::middleware::ratelimit::EXEMPT_SERVICES;
+use crate::otel::METRICS;
+
+
+// Server-wide limits, every setting is optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestLimits {
+    pub max_concurrent: Option<usize>,
+    pub max_concurrent_per_connection: Option<usize>,
+    pub default_timeout: Option<Duration>,
+    pub method_timeouts: HashMap<String, Duration>,
+}
+
+impl RequestLimits {
+    // REQUEST_TIMEOUT_METHODS has "Method=milliseconds" pairs separated by commas
+    pub fn from_env() -> Result<Self, String> {
+        let parse = |name: &str, value: &str| value.trim().parse::<u64>()
+            .map_err(|err| format!("{name}: invalid value {value}: {err}"));
+        let env = |name: &str| std::env::var(name).ok()
+            .map(|value| parse(name, &value))
+            .transpose();
+
+        let method_timeouts = std::env::var("REQUEST_TIMEOUT_METHODS").unwrap_or_default()
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((method, timeout)) => Ok((method.trim().to_string(), Duration::from_millis(parse("REQUEST_TIMEOUT_METHODS", timeout)?))),
+                None => Err(format!("REQUEST_TIMEOUT_METHODS: invalid method=milliseconds pair {pair}")),
+            })
+            .collect::<Result<HashMap<String, Duration>, String>>()?;
+
+        Ok(RequestLimits {
+            max_concurrent: env("MAX_CONCURRENT_REQUESTS")?.map(|max| max as usize),
+            max_concurrent_per_connection: env("MAX_CONCURRENT_REQUESTS_PER_CONNECTION")?.map(|max| max as usize),
+            default_timeout: env("REQUEST_TIMEOUT_MS")?.map(Duration::from_millis),
+            method_timeouts,
+        })
+    }
+
+    fn timeout(&self, method: &str) -> Option<Duration> {
+        self.method_timeouts.get(method).copied().or(self.default_timeout)
+    }
+}
+
+
+// The grpc-timeout header is an integer of at most 8 digits followed by a unit
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount = amount.parse::<u64>().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+
+// Rejects requests with Unavailable once `max_concurrent` are in flight, instead of queueing them
+#[derive(Debug, Clone)]
+pub struct LoadShedLayer {
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl LoadShedLayer {
+    pub fn new(max_concurrent: Option<usize>) -> Self {
+        LoadShedLayer {
+            permits: max_concurrent.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+}
+
+impl<S> Layer<S> for LoadShedLayer {
+    type Service = LoadShedService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadShedService { inner, permits: self.permits.clone() }
+    }
+}
+
+
+// Added to the requests that took a slot, a streamed response keeps the slot taken until its end by
+// holding a clone
+#[derive(Debug, Clone)]
+pub struct ConcurrencyPermit {
+    _permit: Arc<OwnedSemaphorePermit>,
+}
+
+
+#[derive(Debug, Clone)]
+pub struct LoadShedService<S> {
+    inner: S,
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for LoadShedService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Default + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        let (service, method) = grpc_service_and_method(request.uri().path());
+        let Some(permits) = self.permits.as_ref().filter(|_| !EXEMPT_SERVICES.contains(&service)) else {
+            return Box::pin(self.inner.call(request));
+        };
+
+        match permits.clone().try_acquire_owned() {
+            Ok(permit) => {
+                let permit = ConcurrencyPermit {_permit: Arc::new(permit)};
+                request.extensions_mut().insert(permit.clone());
+                let future = self.inner.call(request);
+                Box::pin(async move {
+                    let response = future.await;
+                    drop(permit);
+                    response
+                })
+            },
+            Err(_) => {
+                METRICS.record_throttled(service, method, "concurrency");
+                let status = Status::new(Code::Unavailable, "the server is overloaded, retry later");
+                Box::pin(async move { Ok(status_response(&status)) })
+            },
+        }
+    }
+}
+
+
+// Bounds every request by the earliest of its grpc-timeout and the configured method timeout.
+// The deadline is made available to the database layer so it can cancel the running query.
+#[derive(Debug, Clone)]
+pub struct DeadlineLayer {
+    limits: Arc<RequestLimits>,
+}
+
+impl DeadlineLayer {
+    pub fn new(limits: RequestLimits) -> Self {
+        DeadlineLayer { limits: Arc::new(limits) }
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = DeadlineService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeadlineService { inner, limits: self.limits.clone() }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct DeadlineService<S> {
+    inner: S,
+    limits: Arc<RequestLimits>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for DeadlineService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Default + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let (_, method) = grpc_service_and_method(request.uri().path());
+        let client_timeout = request.headers()
+            .get("grpc-timeout")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_grpc_timeout);
+        let timeout = match (client_timeout, self.limits.timeout(method)) {
+            (Some(client), Some(server)) => Some(client.min(server)),
+            (client, server) => client.or(server),
+        };
+
+        let future = self.inner.call(request);
+        let Some(timeout) = timeout else {
+            return Box::pin(future);
+        };
+
+        // Dropping the handler future on expiry drops its pending query as well
+        let deadline = Instant::now() + timeout;
+        Box::pin(async move {
+            match tokio::time::timeout_at(deadline.into(), with_deadline(deadline, future)).await {
+                Ok(response) => response,
+                Err(_) => Ok(status_response(&Status::new(Code::DeadlineExceeded, "deadline exceeded"))),
+            }
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use crate::database::deadline::remaining;
+    use crate::middleware::metrics::response_code;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("1H"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("10x"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_limits_from_env() {
+        std::env::set_var("MAX_CONCURRENT_REQUESTS", "100");
+        std::env::set_var("REQUEST_TIMEOUT_MS", "5000");
+        std::env::set_var("REQUEST_TIMEOUT_METHODS", "GetAgendas=2000");
+
+        let limits = RequestLimits::from_env().unwrap();
+
+        assert_eq!(limits.max_concurrent, Some(100));
+        assert_eq!(limits.max_concurrent_per_connection, None);
+        assert_eq!(limits.timeout("GetAgendas"), Some(Duration::from_millis(2000)));
+        assert_eq!(limits.timeout("GetAgenda"), Some(Duration::from_millis(5000)));
+
+        std::env::remove_var("MAX_CONCURRENT_REQUESTS");
+        std::env::remove_var("REQUEST_TIMEOUT_MS");
+        std::env::remove_var("REQUEST_TIMEOUT_METHODS");
+    }
+
+    #[tokio::test]
+    async fn test_load_shed() {
+        let mut service = LoadShedLayer::new(Some(1)).layer(tower::service_fn(|_request: http::Request<()>| async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body()))
+        }));
+
+        let first = service.call(http::Request::new(()));
+        let second = service.call(http::Request::new(()));
+
+        assert_eq!(response_code(&second.await), Code::Unavailable);
+        assert_eq!(response_code(&first.await), Code::Ok);
+        assert_eq!(response_code(&service.call(http::Request::new(())).await), Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_load_shed_streaming_permit() {
+        let kept = Arc::new(std::sync::Mutex::new(None));
+        let handler_kept = Arc::clone(&kept);
+        let mut service = LoadShedLayer::new(Some(1)).layer(tower::service_fn(move |request: http::Request<()>| {
+            *handler_kept.lock().unwrap() = request.extensions().get::<ConcurrencyPermit>().cloned();
+            async { Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body())) }
+        }));
+
+        // The slot stays taken after the response while the stream holds the permit
+        assert_eq!(response_code(&service.call(http::Request::new(())).await), Code::Ok);
+        let permit = kept.lock().unwrap().take();
+        assert!(permit.is_some());
+        assert_eq!(response_code(&service.call(http::Request::new(())).await), Code::Unavailable);
+
+        drop(permit);
+        assert_eq!(response_code(&service.call(http::Request::new(())).await), Code::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let limits = RequestLimits {
+            method_timeouts: HashMap::from([("Slow".to_string(), Duration::from_millis(20))]),
+            ..Default::default()
+        };
+        let mut service = DeadlineLayer::new(limits).layer(tower::service_fn(|request: http::Request<()>| async move {
+            assert!(remaining().is_some());
+            if request.uri().path().ends_with("/Slow") {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body()))
+        }));
+
+        let slow = http::Request::builder().uri("/agenda.v1.AgendaService/Slow").body(()).unwrap();
+        let fast = http::Request::builder().uri("/agenda.v1.AgendaService/Fast").header("grpc-timeout", "1S").body(()).unwrap();
+
+        assert_eq!(response_code(&service.call(slow).await), Code::DeadlineExceeded);
+        assert_eq!(response_code(&service.call(fast).await), Code::Ok);
+    }
+}
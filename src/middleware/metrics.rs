@@ -0,0 +1,213 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use pin_project::{pin_project, pinned_drop};
+use tonic::{Code, Status};
+use tonic::body::BoxBody;
+use tonic::codegen::StdError;
+use tower::{Layer, Service};
+use crate::middleware::{grpc_service_and_method, BoxFuture};
+use crate::otel::METRICS;
+
+
+// Records request count, latency and status code of every RPC going through the server
+#[derive(Debug, Clone, Default)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsService { inner }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct MetricsService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for MetricsService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<StdError>,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let (service, method) = grpc_service_and_method(request.uri().path());
+        let call = RpcCall {service: service.to_string(), method: method.to_string(), start: Instant::now()};
+        let future = self.inner.call(request);
+
+        Box::pin(async move {
+            match future.await {
+                Ok(response) => {
+                    let (parts, inner) = response.into_parts();
+                    let body = MetricsBody::new(&parts.headers, inner, call);
+                    Ok(http::Response::from_parts(parts, tonic::body::boxed(body)))
+                }
+                Err(err) => {
+                    finish(&mut Some(call), &mut None, Code::Unknown);
+                    Err(err)
+                }
+            }
+        })
+    }
+}
+
+
+struct RpcCall {
+    service: String,
+    method: String,
+    start: Instant,
+}
+
+
+// Records the RPC once its status is known, so errors sent in the trailers and streaming calls are
+// measured until their end. A body dropped before its trailers is a cancelled call.
+#[pin_project(PinnedDrop)]
+pub struct MetricsBody<B> {
+    #[pin]
+    inner: B,
+    call: Option<RpcCall>,
+    code: Option<Code>,
+}
+
+impl<B> MetricsBody<B> {
+    // Trailers-only responses carry the status in their headers, others in the trailers of the body
+    fn new(headers: &http::HeaderMap, inner: B, call: RpcCall) -> Self {
+        let (mut call, mut code) = (Some(call), None);
+        if let Some(status) = Status::from_header_map(headers) {
+            finish(&mut call, &mut code, status.code());
+        }
+        MetricsBody {inner, call, code}
+    }
+}
+
+fn finish(call: &mut Option<RpcCall>, recorded: &mut Option<Code>, code: Code) {
+    if let Some(call) = call.take() {
+        METRICS.record_rpc(&call.service, &call.method, code, call.start.elapsed().as_secs_f64() * 1000.0);
+        *recorded = Some(code);
+    }
+}
+
+impl<B: Body> Body for MetricsBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = ready!(this.inner.poll_frame(cx));
+        match &frame {
+            Some(Ok(frame)) => if let Some(trailers) = frame.trailers_ref() {
+                let code = Status::from_header_map(trailers).map(|status| status.code()).unwrap_or(Code::Unknown);
+                finish(this.call, this.code, code);
+            },
+            // A gRPC response always ends with its status
+            Some(Err(_)) | None => finish(this.call, this.code, Code::Unknown),
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for MetricsBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        finish(this.call, this.code, Code::Cancelled);
+    }
+}
+
+
+// Errors are sent as trailers-only responses, so a missing status in the headers means the call succeeded
+pub fn response_code<ResBody, E>(response: &Result<http::Response<ResBody>, E>) -> Code {
+    match response {
+        Ok(response) => Status::from_header_map(response.headers())
+            .map(|status| status.code())
+            .unwrap_or(Code::Ok),
+        Err(_) => Code::Unknown,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use super::*;
+
+    // Sends its frames in order
+    #[derive(Default)]
+    struct FramesBody(VecDeque<Frame<Bytes>>);
+
+    impl Body for FramesBody {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_frame(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+            Poll::Ready(self.0.pop_front().map(Ok))
+        }
+    }
+
+    fn metrics_body(headers: &[(&'static str, &'static str)], frames: Vec<Frame<Bytes>>) -> MetricsBody<FramesBody> {
+        let mut header_map = http::HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(*name, value.parse().unwrap());
+        }
+        let call = RpcCall {service: "agenda.v1.AgendaService".into(), method: "GetAgenda".into(), start: Instant::now()};
+        MetricsBody::new(&header_map, FramesBody(frames.into()), call)
+    }
+
+    #[tokio::test]
+    async fn test_metrics_body() {
+        // A trailers-only response is recorded as soon as it is returned
+        let body = metrics_body(&[("grpc-status", "7")], vec![]);
+        assert_eq!(body.code, Some(Code::PermissionDenied));
+
+        // Otherwise the status comes with the trailers, after the messages
+        let mut trailers = http::HeaderMap::new();
+        trailers.insert("grpc-status", "5".parse().unwrap());
+        let mut body = metrics_body(&[], vec![Frame::data(Bytes::from_static(b"message")), Frame::trailers(trailers)]);
+        assert_eq!(body.code, None);
+        while let Some(frame) = std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+            assert!(frame.is_ok());
+        }
+        assert_eq!(body.code, Some(Code::NotFound));
+
+        // A body ending without a status is not a success
+        let mut body = metrics_body(&[], vec![Frame::data(Bytes::from_static(b"message"))]);
+        while std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await.is_some() {}
+        assert_eq!(body.code, Some(Code::Unknown));
+    }
+
+    #[tokio::test]
+    async fn test_response_code() {
+        let ok = Ok::<_, ()>(http::Response::new(()));
+        assert_eq!(response_code(&ok), Code::Ok);
+
+        let mut not_found = http::Response::new(());
+        not_found.headers_mut().insert("grpc-status", "5".parse().unwrap());
+        assert_eq!(response_code(&Ok::<_, ()>(not_found)), Code::NotFound);
+
+        assert_eq!(response_code(&Err::<http::Response<()>, _>(())), Code::Unknown);
+    }
+}
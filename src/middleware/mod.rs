@@ -0,0 +1,42 @@
+pub mod auth;
+pub mod cors;
+pub mod limits;
+pub mod metrics;
+pub mod propagation;
+pub mod ratelimit;
+pub mod routing;
+
+use std::future::Future;
+use std::pin::Pin;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+
+// Splits a gRPC request path such as "/agenda.v1.AgendaService/Ping" into service and method
+pub fn grpc_service_and_method(path: &str) -> (&str, &str) {
+    let mut parts = path.trim_start_matches('/').splitn(2, '/');
+    let service = parts.next().unwrap_or_default();
+    let method = parts.next().unwrap_or_default();
+    (service, method)
+}
+
+
+// A trailers-only gRPC response carrying the status in its headers
+pub fn status_response<B: Default>(status: &tonic::Status) -> http::Response<B> {
+    let mut response = http::Response::new(B::default());
+    response.headers_mut().insert(http::header::CONTENT_TYPE, http::HeaderValue::from_static("application/grpc"));
+    let _ = status.add_header(response.headers_mut());
+    response
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grpc_service_and_method() {
+        assert_eq!(grpc_service_and_method("/agenda.v1.AgendaService/Ping"), ("agenda.v1.AgendaService", "Ping"));
+        assert_eq!(grpc_service_and_method("/"), ("", ""));
+    }
+}
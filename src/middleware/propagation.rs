@@ -0,0 +1,155 @@
+use std::task::{Context, Poll};
+use opentelemetry::global;
+use opentelemetry::propagation::{Extractor, Injector};
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use crate::middleware::{grpc_service_and_method, BoxFuture};
+use crate::middleware::metrics::response_code;
+
+
+pub struct HeaderExtractor<'a>(pub &'a http::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+
+pub struct HeaderInjector<'a>(pub &'a mut http::HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (http::HeaderName::from_bytes(key.as_bytes()), http::HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+
+// Continues the trace of the caller: the context found in the request metadata becomes the parent of
+// the request span, and the context of the request span is returned in the response metadata.
+// The request span carries the RPC semantic convention attributes.
+#[derive(Debug, Clone, Default)]
+pub struct TracePropagationLayer;
+
+impl<S> Layer<S> for TracePropagationLayer {
+    type Service = TracePropagationService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TracePropagationService { inner }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct TracePropagationService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for TracePropagationService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let parent_context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(request.headers()))
+        });
+
+        let (service, method) = grpc_service_and_method(request.uri().path());
+        let peer = request.extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.remote_addr());
+        let span = tracing::info_span!(
+            "grpc_request",
+            otel.name = format!("{service}/{method}"),
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.service = service,
+            rpc.method = method,
+            rpc.grpc.status_code = tracing::field::Empty,
+            net.peer.ip = peer.map(|addr| addr.ip().to_string()),
+            net.peer.port = peer.map(|addr| addr.port()),
+        );
+        span.set_parent(parent_context);
+
+        let future = span.in_scope(|| self.inner.call(request));
+        let response_span = span.clone();
+
+        Box::pin(async move {
+            let response = future.await;
+            response_span.record("rpc.grpc.status_code", response_code(&response) as i32);
+
+            let mut response = response?;
+            let context = response_span.context();
+            global::get_text_map_propagator(|propagator| {
+                propagator.inject_context(&context, &mut HeaderInjector(response.headers_mut()))
+            });
+            Ok(response)
+        }.instrument(span))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::TracerProvider as SDKTracerProvider;
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trace_propagation_service() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = SDKTracerProvider::builder().build().tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let service = TracePropagationLayer.layer(tower::service_fn(|_request: http::Request<()>| async {
+            let trace_id = tracing::Span::current().context().span().span_context().trace_id();
+            Ok::<_, Infallible>(http::Response::new(trace_id.to_string()))
+        }));
+
+        let request = http::Request::builder()
+            .uri("/agenda.v1.AgendaService/Ping")
+            .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .body(())
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+
+        assert_eq!(response.body(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        let traceparent = response.headers().get("traceparent").unwrap().to_str().unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+
+    #[tokio::test]
+    async fn test_header_extractor_injector() {
+        let mut headers = http::HeaderMap::new();
+        HeaderInjector(&mut headers).set("traceparent", "value".to_string());
+        HeaderInjector(&mut headers).set("invalid header", "value".to_string());
+
+        let extractor = HeaderExtractor(&headers);
+        assert_eq!(extractor.get("traceparent"), Some("value"));
+        assert_eq!(extractor.keys(), vec!["traceparent"]);
+    }
+}
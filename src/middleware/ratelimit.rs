@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use indexmap::IndexMap;
+use rand::Rng;
+use tonic::Code;
+use tonic::transport::server::TcpConnectInfo;
+use tonic_types::{ErrorDetails, StatusExt};
+use tower::{Layer, Service};
+use crate::auth::Principal;
+use crate::middleware::{grpc_service_and_method, status_response, BoxFuture};
+use crate::otel::METRICS;
+
+pub const API_KEY_METADATA_KEY: &str = "x-api-key";
+
+// Health checks come from the orchestrator and must never be throttled
+pub(crate) const EXEMPT_SERVICES: [&str; 1] = ["grpc.health.v1.Health"];
+
+// Past this many buckets a random one is evicted for each new one, its client starts over with a full bucket
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+// Idle clients have full buckets, they are forgotten on every sweep
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+
+// Refills at `rate` tokens per second up to `capacity`, each acquired token costs one
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, rate: f64) -> Self {
+        TokenBucket {
+            capacity,
+            rate,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // Time until the next token is available
+    pub fn retry_after(&self) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::try_from_secs_f64(((1.0 - self.tokens) / self.rate).max(0.0)).unwrap_or(Duration::MAX)
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.capacity
+    }
+}
+
+
+// Requests per second allowed for each client, overall and for specific methods
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    pub global: Option<f64>,
+    pub burst: Option<f64>,
+    pub methods: HashMap<String, f64>,
+}
+
+impl RateLimits {
+    // Limits of each client once authenticated, by API key or by address for anonymous requests
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_env_prefix("RATE_LIMIT")
+    }
+
+    // Limits of each address before authentication, a coarse guard that clients sharing a proxy also share
+    pub fn peer_from_env() -> Result<Self, String> {
+        Self::from_env_prefix("RATE_LIMIT_PEER")
+    }
+
+    // <prefix>_METHODS has "Method=rate" pairs separated by commas, such as "GetAgendas=5,CreateAgenda=1"
+    fn from_env_prefix(prefix: &str) -> Result<Self, String> {
+        let parse_rate = |name: &str, value: &str| match value.trim().parse::<f64>() {
+            Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+            Ok(_) => Err(format!("{name}: invalid rate {value}, expected a positive number")),
+            Err(err) => Err(format!("{name}: invalid rate {value}: {err}")),
+        };
+
+        let global = std::env::var(format!("{prefix}_RPS")).ok()
+            .map(|rate| parse_rate(&format!("{prefix}_RPS"), &rate))
+            .transpose()?;
+        let burst = std::env::var(format!("{prefix}_BURST")).ok()
+            .map(|burst| parse_rate(&format!("{prefix}_BURST"), &burst))
+            .transpose()?;
+        let methods = std::env::var(format!("{prefix}_METHODS")).unwrap_or_default()
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((method, rate)) => Ok((method.trim().to_string(), parse_rate(&format!("{prefix}_METHODS"), rate)?)),
+                None => Err(format!("{prefix}_METHODS: invalid method=rate pair {pair}")),
+            })
+            .collect::<Result<HashMap<String, f64>, String>>()?;
+
+        Ok(RateLimits {global, burst, methods})
+    }
+
+    fn is_empty(&self) -> bool {
+        self.global.is_none() && self.methods.is_empty()
+    }
+}
+
+
+// What the buckets of a RateLimitLayer are keyed on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitBy {
+    // The peer address, the only thing known before authentication that a client cannot change at will
+    Peer,
+    // The verified API key, or the peer address for requests without one
+    Client,
+}
+
+pub fn client_key<B>(request: &http::Request<B>, by: RateLimitBy) -> Option<String> {
+    match by {
+        RateLimitBy::Peer => Some(request.extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.remote_addr())
+            .map(|addr| format!("ip:{}", addr.ip()))
+            .unwrap_or("unknown".to_string())),
+        RateLimitBy::Client => match request.extensions().get::<Principal>() {
+            Some(principal) => Some(format!("key:{}", principal.key_id)),
+            None => client_key(request, RateLimitBy::Peer),
+        },
+    }
+}
+
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: IndexMap<(String, Option<String>), TokenBucket>,
+    last_sweep: Instant,
+}
+
+impl Buckets {
+    fn new() -> Self {
+        Buckets {buckets: IndexMap::new(), last_sweep: Instant::now()}
+    }
+
+    fn get_or_insert(&mut self, key: (String, Option<String>), bucket: impl FnOnce() -> TokenBucket) -> &mut TokenBucket {
+        if self.buckets.len() >= MAX_TRACKED_BUCKETS && !self.buckets.contains_key(&key) {
+            let index = rand::thread_rng().gen_range(0..self.buckets.len());
+            self.buckets.swap_remove_index(index);
+        }
+        self.buckets.entry(key).or_insert_with(bucket)
+    }
+
+    fn sweep(&mut self) {
+        if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+            self.buckets.retain(|_, bucket| !bucket.is_full());
+            self.last_sweep = Instant::now();
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    // Takes a token from the client bucket and from the method bucket, returning the limit that
+    // rejected the request and when to retry
+    fn check(&self, client: &str, method: &str) -> Result<(), (&'static str, Duration)> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        buckets.sweep();
+
+        let limits = [
+            ("global", None, self.limits.global),
+            ("method", Some(method.to_string()), self.limits.methods.get(method).copied()),
+        ];
+        for (limit, bucket_method, rate) in limits {
+            let Some(rate) = rate else { continue };
+            let burst = match limit {
+                "global" => self.limits.burst.unwrap_or(rate),
+                _ => rate,
+            };
+            let bucket = buckets.get_or_insert((client.to_string(), bucket_method), || TokenBucket::new(burst.max(1.0), rate));
+            if !bucket.try_acquire() {
+                return Err((limit, bucket.retry_after()));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+// Throttles every client to the configured rates, rejected calls get ResourceExhausted with RetryInfo.
+// It goes before the authentication keyed by peer, and after it keyed by API key, with their own limits.
+#[derive(Debug, Clone)]
+pub struct RateLimitLayer {
+    by: RateLimitBy,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limits: RateLimits, by: RateLimitBy) -> Self {
+        RateLimitLayer {
+            by,
+            limiter: (!limits.is_empty()).then(|| Arc::new(RateLimiter {
+                limits,
+                buckets: Mutex::new(Buckets::new()),
+            })),
+        }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService { inner, by: self.by, limiter: self.limiter.clone() }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    by: RateLimitBy,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: Default + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let (service, method) = grpc_service_and_method(request.uri().path());
+
+        let limiter = self.limiter.as_ref().filter(|_| !EXEMPT_SERVICES.contains(&service));
+        if let Some((limiter, client)) = limiter.and_then(|limiter| Some((limiter, client_key(&request, self.by)?))) {
+            if let Err((limit, retry_after)) = limiter.check(&client, method) {
+                METRICS.record_throttled(service, method, limit);
+                let status = tonic::Status::with_error_details(
+                    Code::ResourceExhausted,
+                    format!("rate limit exceeded, retry in {} ms", retry_after.as_millis()),
+                    ErrorDetails::with_retry_info(Some(retry_after)),
+                );
+                return Box::pin(async move { Ok(status_response(&status)) });
+            }
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limits: RateLimits) -> RateLimiter {
+        RateLimiter {limits, buckets: Mutex::new(Buckets::new())}
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(2.0, 0.0);
+
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_retry_after() {
+        let mut bucket = TokenBucket::new(1.0, 2.0);
+
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+        assert!(bucket.retry_after() <= Duration::from_millis(500));
+        assert!(bucket.retry_after() > Duration::from_millis(400));
+
+        let mut bucket = TokenBucket::new(1.0, f64::MIN_POSITIVE);
+        assert!(bucket.try_acquire());
+        assert_eq!(bucket.retry_after(), Duration::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_from_env() {
+        std::env::set_var("RATE_LIMIT_RPS", "10");
+        std::env::set_var("RATE_LIMIT_METHODS", "GetAgendas=5, CreateAgenda=0.5");
+
+        let limits = RateLimits::from_env().unwrap();
+
+        assert_eq!(limits.global, Some(10.0));
+        assert_eq!(limits.burst, None);
+        assert_eq!(limits.methods.get("CreateAgenda"), Some(&0.5));
+        assert_eq!(RateLimits::peer_from_env(), Ok(RateLimits::default()));
+
+        std::env::set_var("RATE_LIMIT_PEER_RPS", "100");
+        assert_eq!(RateLimits::peer_from_env().unwrap().global, Some(100.0));
+        std::env::remove_var("RATE_LIMIT_PEER_RPS");
+
+        std::env::set_var("RATE_LIMIT_METHODS", "GetAgendas");
+        assert!(RateLimits::from_env().is_err());
+        for rate in ["0", "-1", "inf", "NaN"] {
+            std::env::set_var("RATE_LIMIT_METHODS", format!("GetAgendas={rate}"));
+            assert!(RateLimits::from_env().is_err());
+        }
+
+        std::env::remove_var("RATE_LIMIT_RPS");
+        std::env::remove_var("RATE_LIMIT_METHODS");
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_per_client() {
+        let limiter = limiter(RateLimits {global: Some(1.0), ..Default::default()});
+
+        assert!(limiter.check("ip:10.0.0.1", "GetAgenda").is_ok());
+        assert_eq!(limiter.check("ip:10.0.0.1", "GetAgenda").map_err(|(limit, _)| limit), Err("global"));
+        assert!(limiter.check("ip:10.0.0.2", "GetAgenda").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_per_method() {
+        let limiter = limiter(RateLimits {
+            methods: HashMap::from([("CreateAgenda".to_string(), 1.0)]),
+            ..Default::default()
+        });
+
+        assert!(limiter.check("ip:10.0.0.1", "CreateAgenda").is_ok());
+        assert_eq!(limiter.check("ip:10.0.0.1", "CreateAgenda").map_err(|(limit, _)| limit), Err("method"));
+        assert!(limiter.check("ip:10.0.0.1", "GetAgenda").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_bounded() {
+        let limiter = limiter(RateLimits {global: Some(1.0), ..Default::default()});
+
+        // Every client is still limited, a new one evicts another instead of growing the map
+        for client in 0..=MAX_TRACKED_BUCKETS {
+            assert!(limiter.check(&format!("ip:{client}"), "GetAgenda").is_ok());
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), MAX_TRACKED_BUCKETS);
+        assert!(buckets.buckets.contains_key(&(format!("ip:{MAX_TRACKED_BUCKETS}"), None)));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_sweep() {
+        let limiter = limiter(RateLimits {global: Some(20.0), burst: Some(1.0), ..Default::default()});
+
+        assert!(limiter.check("ip:10.0.0.1", "GetAgenda").is_ok());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Only the buckets refilled since their last request are forgotten, and only once the interval elapsed
+        assert!(limiter.check("ip:10.0.0.2", "GetAgenda").is_ok());
+        assert_eq!(limiter.buckets.lock().unwrap().buckets.len(), 2);
+
+        limiter.buckets.lock().unwrap().last_sweep -= SWEEP_INTERVAL;
+        assert!(limiter.check("ip:10.0.0.3", "GetAgenda").is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.keys().cloned().collect::<Vec<_>>(), vec![("ip:10.0.0.2".to_string(), None), ("ip:10.0.0.3".to_string(), None)]);
+    }
+
+    #[tokio::test]
+    async fn test_client_key() {
+        // The unverified header never selects a bucket
+        let mut request = http::Request::builder()
+            .header(API_KEY_METADATA_KEY, "agk_1_random")
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(TcpConnectInfo {local_addr: None, remote_addr: Some("10.0.0.1:4000".parse().unwrap())});
+        assert_eq!(client_key(&request, RateLimitBy::Peer).as_deref(), Some("ip:10.0.0.1"));
+        assert_eq!(client_key(&request, RateLimitBy::Client).as_deref(), Some("ip:10.0.0.1"));
+
+        request.extensions_mut().insert(Principal {key_id: 7, name: "ci".into(), scopes: vec![]});
+        assert_eq!(client_key(&request, RateLimitBy::Client).as_deref(), Some("key:7"));
+        assert_eq!(client_key(&request, RateLimitBy::Peer).as_deref(), Some("ip:10.0.0.1"));
+        assert_eq!(client_key(&http::Request::new(()), RateLimitBy::Peer).as_deref(), Some("unknown"));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_service_rejects() {
+        let layer = RateLimitLayer::new(RateLimits {global: Some(1.0), ..Default::default()}, RateLimitBy::Peer);
+        let mut service = layer.layer(tower::service_fn(|_request: http::Request<()>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body()))
+        }));
+        let request = || http::Request::builder().uri("/agenda.v1.AgendaService/GetAgenda").body(()).unwrap();
+
+        let allowed = service.call(request()).await.unwrap();
+        let rejected = service.call(request()).await.unwrap();
+
+        assert_eq!(crate::middleware::metrics::response_code(&Ok::<_, ()>(allowed)), Code::Ok);
+        let status = tonic::Status::from_header_map(rejected.headers()).unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert!(status.get_details_retry_info().and_then(|info| info.retry_delay).is_some());
+
+        // Keys sharing an address each get their own quota after authentication
+        let layer = RateLimitLayer::new(RateLimits {global: Some(1.0), ..Default::default()}, RateLimitBy::Client);
+        let mut service = layer.layer(tower::service_fn(|_request: http::Request<()>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body()))
+        }));
+        for key_id in 0..3 {
+            let mut request = request();
+            request.extensions_mut().insert(Principal {key_id, name: "ci".into(), scopes: vec![]});
+            let response = service.call(request).await;
+            assert_eq!(crate::middleware::metrics::response_code(&response), Code::Ok);
+        }
+        let response = service.call(request()).await;
+        assert_eq!(crate::middleware::metrics::response_code(&response), Code::Ok);
+        let response = service.call(request()).await;
+        assert_eq!(crate::middleware::metrics::response_code(&response), Code::ResourceExhausted);
+    }
+}
@@ -0,0 +1,85 @@
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+use crate::database::routing::with_read_from_replica;
+use crate::middleware::{grpc_service_and_method, BoxFuture};
+
+// Clients set it to "true" to read their own writes, which replicas may not have applied yet
+pub const READ_FROM_PRIMARY_METADATA_KEY: &str = "x-read-from-primary";
+
+// Only these methods read from replicas, writes must also read their before snapshot from the primary
+const REPLICA_METHODS: [&str; 4] = ["GetAgenda", "GetAgendas", "ListAgendaHistory", "ExportAgendas"];
+
+
+pub fn reads_from_replica<B>(request: &http::Request<B>) -> bool {
+    let (_, method) = grpc_service_and_method(request.uri().path());
+    let read_from_primary = request.headers()
+        .get(READ_FROM_PRIMARY_METADATA_KEY)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    REPLICA_METHODS.contains(&method) && !read_from_primary
+}
+
+
+// Decides for every RPC whether its database reads may be served by a replica
+#[derive(Debug, Clone, Default)]
+pub struct ReadRoutingLayer;
+
+impl<S> Layer<S> for ReadRoutingLayer {
+    type Service = ReadRoutingService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReadRoutingService { inner }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct ReadRoutingService<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for ReadRoutingService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let read_from_replica = reads_from_replica(&request);
+        let future = self.inner.call(request);
+
+        Box::pin(with_read_from_replica(read_from_replica, future))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(path: &str, read_from_primary: Option<&str>) -> http::Request<()> {
+        let mut builder = http::Request::builder().uri(path);
+        if let Some(value) = read_from_primary {
+            builder = builder.header(READ_FROM_PRIMARY_METADATA_KEY, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reads_from_replica() {
+        assert!(reads_from_replica(&request("/agenda.v1.AgendaService/GetAgenda", None)));
+        assert!(reads_from_replica(&request("/agenda.v1.AgendaService/GetAgendas", Some("false"))));
+        assert!(!reads_from_replica(&request("/agenda.v1.AgendaService/GetAgenda", Some("true"))));
+        assert!(!reads_from_replica(&request("/agenda.v1.AgendaService/UpdateAgenda", None)));
+        assert!(reads_from_replica(&request("/agenda.v1.AgendaService/ExportAgendas", None)));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::admin::ApiKey;
+use crate::model::audit::micros_to_timestamp;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiKeyModel {
+    pub id: i64,
+    pub name: String,
+    // Argon2 hash in PHC string format
+    pub secret_hash: String,
+    pub scopes: Vec<String>,
+    // Microseconds since the unix epoch, as stored by the database
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+}
+
+
+impl ApiKeyModel {
+    pub fn new(name: String, secret_hash: String, scopes: Vec<String>, expires_at: Option<i64>) -> Self {
+        ApiKeyModel {
+            id: 0,
+            name,
+            secret_hash,
+            scopes,
+            created_at: 0,
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        }
+    }
+
+    pub fn is_active(&self, now: i64) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    pub fn to_proto(&self) -> ApiKey {
+        ApiKey {
+            id: self.id,
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            created_at: Some(micros_to_timestamp(self.created_at)),
+            expires_at: self.expires_at.map(micros_to_timestamp),
+            last_used_at: self.last_used_at.map(micros_to_timestamp),
+            revoked_at: self.revoked_at.map(micros_to_timestamp),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_api_key_model_is_active() {
+        let mut key = ApiKeyModel::new("ci".into(), "hash".into(), vec![], Some(1_000));
+
+        assert!(key.is_active(999));
+        assert!(!key.is_active(1_000));
+
+        key.expires_at = None;
+        assert!(key.is_active(i64::MAX));
+
+        key.revoked_at = Some(0);
+        assert!(!key.is_active(0));
+    }
+
+    #[tokio::test]
+    async fn test_api_key_model_to_proto() {
+        let key = ApiKeyModel::new("ci".into(), "hash".into(), vec!["agendas:read".into()], None);
+
+        let proto = key.to_proto();
+
+        assert_eq!(proto.name, "ci");
+        assert_eq!(proto.scopes, vec!["agendas:read".to_string()]);
+        assert_eq!(proto.expires_at, None);
+    }
+}
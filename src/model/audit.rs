@@ -0,0 +1,103 @@
+use prost_types::Timestamp;
+use crate::agenda::AgendaRevision;
+use crate::model::AgendaModel;
+use crate::model::error::ModelError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditModel {
+    pub revision: i64,
+    pub agenda_id: i64,
+    pub actor: String,
+    pub method: String,
+    pub before: Option<AgendaModel>,
+    pub after: Option<AgendaModel>,
+    // Microseconds since the unix epoch, as stored by the database
+    pub changed_at: i64,
+}
+
+
+impl AuditModel {
+    pub fn new(actor: String, method: &str, before: Option<AgendaModel>, after: Option<AgendaModel>) -> Self {
+        let agenda_id = after.as_ref()
+            .or(before.as_ref())
+            .map(|agenda| agenda.id)
+            .unwrap_or(0);
+
+        AuditModel {
+            revision: 0,
+            agenda_id,
+            actor,
+            method: method.to_string(),
+            before,
+            after,
+            changed_at: 0,
+        }
+    }
+
+    pub fn to_proto(&self) -> AgendaRevision {
+        AgendaRevision {
+            revision: self.revision,
+            agenda_id: self.agenda_id,
+            actor: self.actor.clone(),
+            method: self.method.clone(),
+            before: self.before.as_ref().map(|agenda| agenda.to_proto()),
+            after: self.after.as_ref().map(|agenda| agenda.to_proto()),
+            changed_at: Some(micros_to_timestamp(self.changed_at)),
+        }
+    }
+}
+
+
+pub fn micros_to_timestamp(micros: i64) -> Timestamp {
+    Timestamp {
+        seconds: micros.div_euclid(1_000_000),
+        nanos: (micros.rem_euclid(1_000_000) * 1_000) as i32,
+    }
+}
+
+
+// Timestamps whose microseconds do not fit in an i64 are an error rather than an overflow
+pub fn timestamp_to_micros(timestamp: &Timestamp) -> Result<i64, ModelError> {
+    timestamp.seconds.checked_mul(1_000_000)
+        .and_then(|micros| micros.checked_add((timestamp.nanos / 1_000) as i64))
+        .ok_or(ModelError::InvalidTimestamp {seconds: timestamp.seconds, nanos: timestamp.nanos})
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_audit_model_new() {
+        let am = AgendaModel{
+            id: 3,
+            name: "name".into(),
+            email: "email".into(),
+            phone: "phone".into(),
+        };
+
+        let created = AuditModel::new("actor".into(), "CreateAgenda", None, Some(am.clone()));
+        assert_eq!(created.agenda_id, 3);
+
+        let deleted = AuditModel::new("actor".into(), "DeleteAgenda", Some(am.clone()), None);
+        assert_eq!(deleted.agenda_id, 3);
+        assert_eq!(deleted.to_proto().before, Some(am.to_proto()));
+        assert_eq!(deleted.to_proto().after, None);
+    }
+
+    #[tokio::test]
+    async fn test_micros_to_timestamp() {
+        assert_eq!(micros_to_timestamp(1_500_000), Timestamp{seconds: 1, nanos: 500_000_000});
+        assert_eq!(micros_to_timestamp(-1), Timestamp{seconds: -1, nanos: 999_999_000});
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_to_micros() {
+        assert_eq!(timestamp_to_micros(&Timestamp{seconds: 1, nanos: 500_000_999}), Ok(1_500_000));
+        assert_eq!(timestamp_to_micros(&micros_to_timestamp(-1)), Ok(-1));
+        assert_eq!(timestamp_to_micros(&micros_to_timestamp(i64::MAX)), Ok(i64::MAX));
+        assert_eq!(timestamp_to_micros(&Timestamp{seconds: i64::MAX, nanos: 0}), Err(ModelError::InvalidTimestamp {seconds: i64::MAX, nanos: 0}));
+        assert!(timestamp_to_micros(&Timestamp{seconds: i64::MIN / 1_000_000 - 1, nanos: 0}).is_err());
+    }
+}
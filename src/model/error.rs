@@ -5,6 +5,8 @@ use tonic::{Code, Status};
 #[derive(Debug, Clone, PartialEq)]
 pub enum ModelError{
     EmptyInput,
+    InvalidUpdateMask{path: String},
+    InvalidTimestamp{seconds: i64, nanos: i32},
     UnknownError{error: String},
 }
 
@@ -12,6 +14,8 @@ impl fmt::Display for ModelError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ModelError::EmptyInput => write!(f, "missing agenda object in input"),
+            ModelError::InvalidUpdateMask{path} => write!(f, "invalid update mask path {}, expected name, email or phone", path),
+            ModelError::InvalidTimestamp{seconds, nanos} => write!(f, "timestamp out of range: {}s {}ns", seconds, nanos),
             ModelError::UnknownError{error} => write!(f, "internal error: {}", error),
         }
     }
@@ -34,7 +38,7 @@ impl From<ModelError> for Status {
     fn from(err: ModelError) -> Self {
         match err {
             ModelError::UnknownError {error} => Status::new(Code::Internal, error),
-            ModelError::EmptyInput => Status::new(Code::InvalidArgument, err.to_string()),
+            ModelError::EmptyInput | ModelError::InvalidUpdateMask {..} | ModelError::InvalidTimestamp {..} => Status::new(Code::InvalidArgument, err.to_string()),
         }
     }
 }
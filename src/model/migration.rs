@@ -0,0 +1,14 @@
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationModel {
+    pub version: i64,
+    pub name: String,
+    // Microseconds since the unix epoch, None while the migration is pending
+    pub applied_at: Option<i64>,
+}
+
+
+impl MigrationModel {
+    pub fn is_applied(&self) -> bool {
+        self.applied_at.is_some()
+    }
+}
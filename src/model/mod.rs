@@ -1,7 +1,13 @@
 mod error;
+mod audit;
+mod api_key;
+mod migration;
 
 use crate::agenda::Agenda;
 use crate::model::error::ModelError;
+pub use crate::model::audit::{AuditModel, micros_to_timestamp, timestamp_to_micros};
+pub use crate::model::api_key::ApiKeyModel;
+pub use crate::model::migration::MigrationModel;
 
 #[derive(Debug,Clone, PartialEq)]
 pub struct AgendaModel {
@@ -13,7 +19,7 @@ pub struct AgendaModel {
 
 
 impl AgendaModel {
-    pub fn to_proto(self) -> Agenda {
+    pub fn to_proto(&self) -> Agenda {
         Agenda{
             id: self.id,
             name: self.name.clone(),
@@ -34,6 +40,25 @@ impl AgendaModel {
             None => Err(ModelError::EmptyInput),
         }
     }
+
+    // Takes the fields named by the update mask from `update`, all of them when the mask is empty
+    pub fn merge(&self, update: AgendaModel, paths: &[String]) -> Result<Self, ModelError> {
+        if paths.is_empty() {
+            return Ok(AgendaModel {id: self.id, ..update});
+        }
+        let mut merged = self.clone();
+        for path in paths {
+            match path.as_str() {
+                "name" => merged.name = update.name.clone(),
+                "email" => merged.email = update.email.clone(),
+                "phone" => merged.phone = update.phone.clone(),
+                // The id comes from the request, not from the agenda
+                "id" => {}
+                _ => return Err(ModelError::InvalidUpdateMask {path: path.clone()}),
+            }
+        }
+        Ok(merged)
+    }
 }
 
 
@@ -58,4 +83,14 @@ mod tests {
         assert_eq!(ModelError::EmptyInput, AgendaModel::from_proto(None).err().unwrap());
         
     }
+
+    #[tokio::test]
+    async fn test_agenda_model_merge() {
+        let stored = AgendaModel {id: 1, name: "name".into(), email: "email".into(), phone: "phone".into()};
+        let update = AgendaModel {id: 0, name: "".into(), email: "new".into(), phone: "".into()};
+
+        assert_eq!(stored.merge(update.clone(), &["email".into()]).unwrap(), AgendaModel {email: "new".into(), ..stored.clone()});
+        assert_eq!(stored.merge(update.clone(), &[]).unwrap(), AgendaModel {id: 1, ..update.clone()});
+        assert!(matches!(stored.merge(update, &["address".into()]), Err(ModelError::InvalidUpdateMask {..})));
+    }
 }
@@ -0,0 +1,143 @@
+use std::fmt;
+use serde_json::{json, Map, Value};
+use tracing::{Event, Subscriber};
+use tracing::field::{Field, Visit};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::registry::LookupSpan;
+use crate::otel::logger::span_context;
+
+
+// Collects the fields of an event as JSON values
+#[derive(Default)]
+pub(super) struct JsonVisitor(pub(super) Map<String, Value>);
+
+impl Visit for JsonVisitor {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+}
+
+
+// One JSON object per line with the event, the span stack from the root and the trace and span ids.
+// Span fields must be formatted with JsonFields so they can be embedded as objects.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+        let mut fields = visitor.0;
+        let message = fields.remove("message").unwrap_or(Value::Null);
+
+        let mut output = Map::new();
+        output.insert("timestamp".to_string(), json!(timestamp));
+        output.insert("level".to_string(), json!(event.metadata().level().as_str()));
+        output.insert("target".to_string(), json!(event.metadata().target()));
+        output.insert("message".to_string(), message);
+        output.insert("fields".to_string(), Value::Object(fields));
+
+        if let Some(scope) = ctx.event_scope() {
+            let mut spans = Vec::new();
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let span_fields = extensions.get::<FormattedFields<N>>()
+                    .and_then(|fields| serde_json::from_str::<Map<String, Value>>(fields).ok())
+                    .unwrap_or_default();
+                spans.push(json!({"name": span.name(), "fields": span_fields}));
+            }
+            output.insert("spans".to_string(), Value::Array(spans));
+        }
+
+        if let Some(span_context) = ctx.lookup_current().and_then(|span| span_context(&span)) {
+            output.insert("trace_id".to_string(), json!(span_context.trace_id().to_string()));
+            output.insert("span_id".to_string(), json!(span_context.span_id().to_string()));
+        }
+
+        writeln!(writer, "{}", Value::Object(output))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::TracerProvider as SDKTracerProvider;
+    use tracing_subscriber::fmt::format::JsonFields;
+    use tracing_subscriber::layer::SubscriberExt;
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_format() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let tracer = SDKTracerProvider::builder().build().tracer("test");
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(tracing_subscriber::fmt::layer()
+                .event_format(JsonFormat)
+                .fmt_fields(JsonFields::new())
+                .with_writer(move || writer.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("test_span", id = 1, success = tracing::field::Empty).entered();
+            span.record("success", true);
+            tracing::info!(items = 2, "test_event");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "test_event");
+        assert_eq!(line["fields"]["items"], 2);
+        assert_eq!(line["spans"][0]["name"], "test_span");
+        assert_eq!(line["spans"][0]["fields"]["id"], 1);
+        assert_eq!(line["spans"][0]["fields"]["success"], true);
+        assert_eq!(line["trace_id"].as_str().map(|id| id.len()), Some(32));
+        assert_eq!(line["span_id"].as_str().map(|id| id.len()), Some(16));
+    }
+}
@@ -1,27 +1,201 @@
 use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use once_cell::sync::OnceCell;
+use opentelemetry::trace::{SpanContext, TraceContextExt, TraceFlags, TraceState};
+use opentelemetry::Key;
+use opentelemetry::logs::LogError;
+use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::logs::{Logger, LoggerProvider};
+use opentelemetry_sdk::runtime;
 use opentelemetry_sdk::trace::Tracer;
-use tracing_loki::BackgroundTask;
-use tracing_loki::url::Url;
-use tracing_subscriber::{fmt, EnvFilter};
-use tracing_subscriber::layer::SubscriberExt;
+use opentelemetry_semantic_conventions::resource::SERVICE_NAME;
+use reqwest::Url;
+use tracing::{Event, Metadata, Subscriber};
+use tokio::task::JoinHandle;
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+use tracing_subscriber::fmt::format::JsonFields;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
 use tracing_subscriber::util::SubscriberInitExt;
+use crate::otel::json::JsonFormat;
+use crate::otel::loki::{self, LokiController, LokiLayer, LokiTask};
+use crate::otel::resource::{loki_labels, RESOURCE};
+
+// Kept so buffered log records can be flushed on shutdown, there is no global logger provider
+static LOGGER_PROVIDER: OnceCell<LoggerProvider> = OnceCell::new();
+
+// The Loki task is stopped and awaited on shutdown so buffered events are pushed
+static LOKI_TASK: OnceCell<(LokiController, Mutex<Option<JoinHandle<()>>>)> = OnceCell::new();
+
+// Allows replacing the filter set from RUST_LOG while the server is running
+static FILTER_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
 
 pub enum LogLayer {
-    Loki(tracing_loki::Layer, BackgroundTask),
+    Loki(LokiLayer, LokiController, LokiTask),
+    // The provider is kept to be flushed on shutdown once the logger is set
+    Otlp(TraceContextBridge<OpenTelemetryTracingBridge<LoggerProvider, Logger>>, LoggerProvider),
     Stdout,
 }
 
+
+// Makes the OpenTelemetry context of the current tracing span active while an event is bridged,
+// so the log records carry the trace and span ids of the span they were emitted in
+pub struct TraceContextBridge<L> {
+    inner: L,
+}
+
+impl<S, L> Layer<S> for TraceContextBridge<L>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    L: Layer<S>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let span_context = ctx.event_span(event).and_then(|span| span_context(&span));
+
+        match span_context {
+            Some(span_context) => {
+                let _guard = opentelemetry::Context::new()
+                    .with_remote_span_context(span_context)
+                    .attach();
+                self.inner.on_event(event, ctx);
+            },
+            None => self.inner.on_event(event, ctx),
+        }
+    }
+}
+
+
+// The OpenTelemetry span context of a tracing span, as assigned by the tracing-opentelemetry layer
+pub(super) fn span_context<S>(span: &SpanRef<'_, S>) -> Option<SpanContext>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let extensions = span.extensions();
+    extensions.get::<OtelData>().and_then(|data| {
+        let trace_id = data.builder.trace_id
+            .unwrap_or(data.parent_cx.span().span_context().trace_id());
+        data.builder.span_id.map(|span_id| {
+            SpanContext::new(trace_id, span_id, TraceFlags::SAMPLED, false, TraceState::default())
+        })
+    })
+}
+
+
+// Events from the exporter stack itself must not be exported, or every export would produce more logs
+fn is_exporter_event(metadata: &Metadata<'_>) -> bool {
+    ["h2", "hyper", "tonic", "tower", "opentelemetry", "opentelemetry_sdk", "opentelemetry_otlp"]
+        .iter()
+        .any(|target| metadata.target() == *target || metadata.target().starts_with(&format!("{target}::")))
+}
+
+// Parses "key=value" pairs separated by commas, as used by OTEL_RESOURCE_ATTRIBUTES
+fn parse_key_values(value: &str) -> Result<Vec<(String, String)>, String> {
+    value.split(',')
+        .map(|pair| pair.trim())
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+            _ => Err(format!("invalid key=value pair: {pair}")),
+        })
+        .collect()
+}
+
+fn env_key_values(name: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    match std::env::var(name) {
+        Ok(value) => parse_key_values(&value).map_err(|err| format!("{name}: {err}").into()),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+// Basic auth takes precedence over a bearer token when both are set
+fn loki_authorization() -> Option<String> {
+    let username = std::env::var("LOKI_USERNAME").ok();
+    let password = std::env::var("LOKI_PASSWORD").unwrap_or_default();
+    let token = std::env::var("LOKI_BEARER_TOKEN").ok();
+
+    match (username, token) {
+        (Some(username), _) => Some(format!("Basic {}", BASE64_STANDARD.encode(format!("{username}:{password}")))),
+        (None, Some(token)) => Some(format!("Bearer {token}")),
+        (None, None) => None,
+    }
+}
+
+fn loki_builder() -> Result<loki::Builder, Box<dyn Error>> {
+    let service_name = RESOURCE.get(Key::new(SERVICE_NAME))
+        .map(|name| name.to_string())
+        .unwrap_or("tonic-server".to_string());
+
+    // Later labels override earlier ones, Loki rejects duplicates
+    let mut labels: Vec<(String, String)> = Vec::new();
+    let all_labels = vec![("service".to_string(), service_name)].into_iter()
+        .chain(loki_labels(&RESOURCE))
+        .chain(env_key_values("LOKI_LABELS")?);
+    for (key, value) in all_labels {
+        labels.retain(|(existing, _)| *existing != key);
+        labels.push((key, value));
+    }
+
+    let mut builder = loki::builder();
+    for (key, value) in labels {
+        builder = builder.label(key, value)?;
+    }
+    for (key, value) in env_key_values("LOKI_EXTRA_FIELDS")? {
+        builder = builder.extra_field(key, value);
+    }
+    if let Some(authorization) = loki_authorization() {
+        builder = builder.http_header("Authorization", authorization)?;
+    }
+    if let Ok(tenant_id) = std::env::var("LOKI_TENANT_ID") {
+        builder = builder.http_header("X-Scope-OrgID", tenant_id)?;
+    }
+    Ok(builder)
+}
+
+// Where logs are sent, None when they are written to stdout
+pub fn logs_endpoint() -> Option<String> {
+    match std::env::var("OTEL_EXPORTER_LOGS").unwrap_or("stdout".to_string()).as_str() {
+        "loki" => Some(std::env::var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT").unwrap_or("http://127.0.0.1:3100".to_string())),
+        "otlp" => Some(std::env::var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT").unwrap_or("http://localhost:4317".to_string())),
+        _ => None,
+    }
+}
+
 fn init_loki_log_provider() -> Result<LogLayer, Box<dyn Error>> {
-    let (layer, task) = tracing_loki::layer(
-        Url::parse(
-            std::env::var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT")
-                .unwrap_or("http://127.0.0.1:3100".to_string())
-                .as_str()
-        ).unwrap(),
-        vec![("service".into(), "tonic-server".into())].into_iter().collect(),
-        vec![].into_iter().collect(),
-    )?;
-    Ok(LogLayer::Loki(layer, task))
+    let url = logs_endpoint().unwrap_or_default();
+    let url = Url::parse(&url).map_err(|err| format!("invalid Loki endpoint {url}: {err}"))?;
+    let parse = |name: &str, default: u64| std::env::var(name)
+        .ok()
+        .map(|value| value.parse::<u64>().map_err(|err| format!("{name}: {err}")))
+        .transpose()
+        .map(|value| value.unwrap_or(default));
+    let batch_size = parse("LOKI_BATCH_SIZE", 1000)?;
+    let batch_interval = parse("LOKI_BATCH_INTERVAL_MS", 0)?;
+
+    let (layer, controller, task) = loki_builder()?
+        .batch_size(batch_size as usize)
+        .batch_interval(Duration::from_millis(batch_interval))
+        .build_url(url)?;
+    Ok(LogLayer::Loki(layer, controller, task))
+}
+
+fn init_otlp_log_provider() -> Result<LogLayer, Box<dyn Error>> {
+    let provider = opentelemetry_otlp::new_pipeline()
+        .logging()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(logs_endpoint().unwrap_or_default()),
+        )
+        .with_resource(RESOURCE.clone())
+        .install_batch(runtime::Tokio)?;
+
+    let bridge = OpenTelemetryTracingBridge::new(&provider);
+    Ok(LogLayer::Otlp(TraceContextBridge { inner: bridge }, provider))
 }
 
 fn init_sdk_log_provider() -> Result<LogLayer, Box<dyn Error>> {
@@ -34,28 +208,188 @@ pub fn get_logger() -> Result<LogLayer, Box<dyn Error>> {
 
     match log_type.as_str() {
         "loki" => init_loki_log_provider(),
+        "otlp" => init_otlp_log_provider(),
         _ => init_sdk_log_provider(),
     }
 }
 
+fn shutdown_logger_provider() -> Result<(), LogError> {
+    match LOGGER_PROVIDER.get() {
+        Some(provider) => provider.shutdown(),
+        None => Ok(()),
+    }
+}
+
+// Pushes the events still buffered by the Loki task or the OTLP logger provider
+pub async fn shutdown_logger() -> Result<(), Box<dyn Error>> {
+    if let Some((controller, task)) = LOKI_TASK.get() {
+        controller.shutdown();
+        let task = task.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(task) = task {
+            tokio::time::timeout(Duration::from_secs(5), task)
+                .await
+                .map_err(|_| "timed out pushing the buffered logs to Loki")??;
+        }
+    }
+    tokio::task::spawn_blocking(shutdown_logger_provider).await??;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+pub fn get_log_format() -> LogFormat {
+    match std::env::var("LOG_FORMAT").unwrap_or("text".to_string()).as_str() {
+        "json" => LogFormat::Json,
+        _ => LogFormat::Text,
+    }
+}
+
+// Logs are always printed when there is no other exporter, LOG_STDOUT=true prints them as well otherwise
+fn print_to_stdout(log_layer: &LogLayer) -> bool {
+    matches!(log_layer, LogLayer::Stdout)
+        || std::env::var("LOG_STDOUT").map(|value| value == "true").unwrap_or(false)
+}
+
+pub fn get_log_filter() -> Option<String> {
+    FILTER_HANDLE.get()
+        .and_then(|handle| handle.with_current(|filter| filter.to_string()).ok())
+}
+
+// Replaces the active filter, returning the directives of the previous one
+pub fn reload_log_filter(filter: EnvFilter) -> Result<String, Box<dyn Error>> {
+    let handle = FILTER_HANDLE.get().ok_or("the logger has not been initialized")?;
+    let previous = handle.with_current(|filter| filter.to_string())?;
+    handle.reload(filter)?;
+    Ok(previous)
+}
+
 pub fn set_logger(log_layer: LogLayer, tracer: Tracer) -> Result<(), Box<dyn Error>> {
+    let (filter, filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    let (text_layer, json_layer) = match (print_to_stdout(&log_layer), get_log_format()) {
+        (false, _) => (None, None),
+        (true, LogFormat::Text) => (Some(fmt::layer()), None),
+        (true, LogFormat::Json) => (None, Some(fmt::layer().event_format(JsonFormat).fmt_fields(JsonFields::new()))),
+    };
 
-    match log_layer {
-        LogLayer::Loki(layer, task) =>{
-            let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-            tokio::spawn(task);
-            tracing_subscriber::registry()
-                .with(EnvFilter::from_default_env())
-                .with(layer)
-                // .with(fmt::layer()) // Remove this comment if you want to also print logs to stdout
-                .with(telemetry)
-                .init();
+    let (loki_layer, otlp_layer) = match log_layer {
+        LogLayer::Loki(layer, controller, task) => {
+            let _ = LOKI_TASK.set((controller, Mutex::new(Some(tokio::spawn(task.run())))));
+            (Some(layer), None)
         },
-        _ => {
-            let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-            tracing_subscriber::registry().with(EnvFilter::from_default_env()).with(fmt::layer()).with(telemetry).init();
-        }
+        LogLayer::Otlp(layer, provider) => {
+            let _ = LOGGER_PROVIDER.set(provider);
+            (None, Some(layer.with_filter(filter_fn(|metadata| !is_exporter_event(metadata)))))
+        },
+        LogLayer::Stdout => (None, None),
     };
 
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(loki_layer)
+        .with(otlp_layer)
+        .with(text_layer)
+        .with(json_layer)
+        .with(telemetry)
+        .init();
+
+    let _ = FILTER_HANDLE.set(filter_handle);
+
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use opentelemetry::trace::{TraceId, TracerProvider};
+    use opentelemetry_sdk::trace::TracerProvider as SDKTracerProvider;
+    use super::*;
+
+    struct CaptureTraceId(Arc<Mutex<Option<TraceId>>>);
+
+    impl<S: Subscriber> Layer<S> for CaptureTraceId {
+        fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+            let trace_id = opentelemetry::Context::current().span().span_context().trace_id();
+            *self.0.lock().unwrap() = Some(trace_id);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_otlp_logger() {
+        std::env::set_var("OTEL_EXPORTER_LOGS", "otlp");
+
+        let result = get_logger();
+
+        // Nothing global is set until the logger is, the provider is shut down with the layer
+        assert!(matches!(result, Ok(LogLayer::Otlp(_, _))));
+
+        std::env::remove_var("OTEL_EXPORTER_LOGS");
+    }
+
+    #[test]
+    fn test_parse_key_values() {
+        assert_eq!(
+            parse_key_values("env=prod, team = agenda,"),
+            Ok(vec![("env".to_string(), "prod".to_string()), ("team".to_string(), "agenda".to_string())])
+        );
+        assert!(parse_key_values("env").is_err());
+        assert!(parse_key_values("=prod").is_err());
+    }
+
+    #[test]
+    fn test_loki_authorization() {
+        std::env::set_var("LOKI_BEARER_TOKEN", "token");
+        assert_eq!(loki_authorization(), Some("Bearer token".to_string()));
+
+        std::env::set_var("LOKI_USERNAME", "user");
+        std::env::set_var("LOKI_PASSWORD", "password");
+        assert_eq!(loki_authorization(), Some("Basic dXNlcjpwYXNzd29yZA==".to_string()));
+
+        std::env::remove_var("LOKI_BEARER_TOKEN");
+        std::env::remove_var("LOKI_USERNAME");
+        std::env::remove_var("LOKI_PASSWORD");
+        assert_eq!(loki_authorization(), None);
+    }
+
+    #[tokio::test]
+    async fn test_loki_logger_config() {
+        std::env::set_var("OTEL_EXPORTER_LOGS", "loki");
+        std::env::set_var("LOKI_LABELS", "service=agenda,env=test");
+        std::env::set_var("LOKI_EXTRA_FIELDS", "region=eu");
+        std::env::set_var("LOKI_TENANT_ID", "tenant");
+
+        assert!(matches!(get_logger(), Ok(LogLayer::Loki(_, _, _))));
+
+        std::env::set_var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT", "not a url");
+        assert!(get_logger().is_err());
+
+        std::env::remove_var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT");
+        std::env::remove_var("LOKI_LABELS");
+        std::env::remove_var("LOKI_EXTRA_FIELDS");
+        std::env::remove_var("LOKI_TENANT_ID");
+        std::env::remove_var("OTEL_EXPORTER_LOGS");
+    }
+
+    #[test]
+    fn test_trace_context_bridge() {
+        let tracer = SDKTracerProvider::builder().build().tracer("test");
+        let captured = Arc::new(Mutex::new(None));
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer))
+            .with(TraceContextBridge { inner: CaptureTraceId(captured.clone()) });
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("test_span").entered();
+            tracing::info!("test_event");
+        });
+
+        let trace_id = captured.lock().unwrap().expect("the event was not bridged");
+        assert_ne!(trace_id, TraceId::INVALID);
+    }
+}
@@ -0,0 +1,439 @@
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::Url;
+use serde_json::{json, Map, Value};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{Event, Level, Subscriber};
+use tracing::instrument::WithSubscriber;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::NoSubscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+use crate::otel::json::JsonVisitor;
+
+// Events kept while Loki cannot be reached, later ones are dropped until a push succeeds
+const MAX_BUFFERED_EVENTS: usize = 100_000;
+// Wait after a failed push before the next attempt
+const FAILED_PUSH_BACKOFF: Duration = Duration::from_secs(1);
+
+
+struct LokiEvent {
+    level: Level,
+    // Nanoseconds since the unix epoch
+    timestamp: u128,
+    line: String,
+}
+
+#[derive(Default)]
+struct Pending {
+    events: Vec<LokiEvent>,
+    dropped: usize,
+    closed: bool,
+}
+
+// Events waiting to be pushed, shared by the layer which adds them and the task which sends them
+struct Batch {
+    pending: Mutex<Pending>,
+    changed: Notify,
+    size: usize,
+}
+
+impl Batch {
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn push(&self, event: LokiEvent) {
+        let mut pending = self.lock();
+        if pending.events.len() >= MAX_BUFFERED_EVENTS {
+            pending.dropped += 1;
+            return;
+        }
+        pending.events.push(event);
+        let buffered = pending.events.len();
+        drop(pending);
+
+        // The task waits for the first event of a batch, then for the batch to be full
+        if buffered == 1 || buffered >= self.size {
+            self.changed.notify_one();
+        }
+    }
+
+    // Failed events go back in front of the ones that arrived since, the oldest are dropped past the limit
+    fn requeue(&self, mut events: Vec<LokiEvent>) {
+        let mut pending = self.lock();
+        events.append(&mut pending.events);
+        let excess = events.len().saturating_sub(MAX_BUFFERED_EVENTS);
+        events.drain(..excess);
+        pending.dropped += excess;
+        pending.events = events;
+    }
+}
+
+
+pub fn builder() -> Builder {
+    Builder {
+        labels: Vec::new(),
+        extra_fields: Map::new(),
+        headers: HeaderMap::new(),
+        batch_size: 1000,
+        batch_interval: Duration::ZERO,
+    }
+}
+
+pub struct Builder {
+    labels: Vec<(String, String)>,
+    extra_fields: Map<String, Value>,
+    headers: HeaderMap,
+    batch_size: usize,
+    batch_interval: Duration,
+}
+
+impl Builder {
+    // Loki label names are identifiers, the level label is set from each event
+    pub fn label(mut self, key: impl Into<String>, value: impl Into<String>) -> Result<Self, Box<dyn Error>> {
+        let key = key.into();
+        let valid = key.chars().enumerate().all(|(index, c)| c == '_' || c.is_ascii_alphabetic() || (index > 0 && c.is_ascii_digit()));
+        if key.is_empty() || !valid {
+            return Err(format!("invalid Loki label {key}").into());
+        }
+        if key == "level" || self.labels.iter().any(|(existing, _)| *existing == key) {
+            return Err(format!("duplicate Loki label {key}").into());
+        }
+        self.labels.push((key, value.into()));
+        Ok(self)
+    }
+
+    pub fn extra_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_fields.insert(key.into(), Value::String(value.into()));
+        self
+    }
+
+    pub fn http_header(mut self, key: &str, value: impl AsRef<str>) -> Result<Self, Box<dyn Error>> {
+        self.headers.insert(HeaderName::from_bytes(key.as_bytes())?, HeaderValue::from_str(value.as_ref())?);
+        Ok(self)
+    }
+
+    // A batch is pushed once it has this many events, or once the interval elapsed since its first event
+    pub fn batch_size(mut self, size: usize) -> Self {
+        self.batch_size = size.max(1);
+        self
+    }
+
+    pub fn batch_interval(mut self, interval: Duration) -> Self {
+        self.batch_interval = interval;
+        self
+    }
+
+    pub fn build_url(self, url: Url) -> Result<(LokiLayer, LokiController, LokiTask), Box<dyn Error>> {
+        let url = url.join("loki/api/v1/push").map_err(|err| format!("invalid Loki endpoint {url}: {err}"))?;
+        let client = reqwest::Client::builder()
+            .default_headers(self.headers)
+            .build()?;
+        let batch = Arc::new(Batch {
+            pending: Mutex::new(Pending::default()),
+            changed: Notify::new(),
+            size: self.batch_size,
+        });
+
+        Ok((
+            LokiLayer {extra_fields: self.extra_fields, batch: Arc::clone(&batch)},
+            LokiController {batch: Arc::clone(&batch)},
+            LokiTask {url, client, labels: self.labels, interval: self.batch_interval, batch},
+        ))
+    }
+}
+
+
+// Fields of a span as they are recorded, copied into the events of the span
+struct SpanFields(Map<String, Value>);
+
+// Serializes events as JSON lines into the batch, the LokiTask pushes them
+pub struct LokiLayer {
+    extra_fields: Map<String, Value>,
+    batch: Arc<Batch>,
+}
+
+impl<S> Layer<S> for LokiLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut visitor = JsonVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.0));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = JsonVisitor(std::mem::take(fields));
+            values.record(&mut visitor);
+            *fields = visitor.0;
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.clone());
+                }
+                spans.push(span.name());
+            }
+        }
+        let mut visitor = JsonVisitor(fields);
+        event.record(&mut visitor);
+        let mut line = visitor.0;
+        line.extend(self.extra_fields.clone());
+
+        let metadata = event.metadata();
+        line.insert("_spans".to_string(), json!(spans));
+        line.insert("_target".to_string(), json!(metadata.target()));
+        line.insert("_module_path".to_string(), json!(metadata.module_path()));
+        line.insert("_file".to_string(), json!(metadata.file()));
+        line.insert("_line".to_string(), json!(metadata.line()));
+
+        self.batch.push(LokiEvent {
+            level: *metadata.level(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos(),
+            line: Value::Object(line).to_string(),
+        });
+    }
+}
+
+
+// Makes the task push what is buffered and stop
+pub struct LokiController {
+    batch: Arc<Batch>,
+}
+
+impl LokiController {
+    pub fn shutdown(&self) {
+        self.batch.lock().closed = true;
+        self.batch.changed.notify_one();
+    }
+}
+
+
+pub struct LokiTask {
+    url: Url,
+    client: reqwest::Client,
+    labels: Vec<(String, String)>,
+    interval: Duration,
+    batch: Arc<Batch>,
+}
+
+impl LokiTask {
+    pub async fn run(self) {
+        loop {
+            let closed = self.wait_for_batch().await;
+            let (events, dropped) = {
+                let mut pending = self.batch.lock();
+                (std::mem::take(&mut pending.events), std::mem::take(&mut pending.dropped))
+            };
+            if dropped > 0 {
+                tracing::warn!("Dropped {} log events, Loki could not keep up", dropped);
+            }
+
+            if !events.is_empty() {
+                let count = events.len();
+                if let Err(err) = self.push(&events).await {
+                    tracing::error!("Error pushing {} log events to Loki: {}", count, err);
+                    if !closed {
+                        self.batch.requeue(events);
+                        tokio::time::sleep(FAILED_PUSH_BACKOFF).await;
+                        continue;
+                    }
+                }
+            }
+            if closed {
+                return;
+            }
+        }
+    }
+
+    // Waits for the first event, then until the batch is full or its interval elapsed.
+    // Returns whether the task was shut down, what is buffered is pushed in any case.
+    async fn wait_for_batch(&self) -> bool {
+        // A notification sent before the wait starts is kept, so no event is missed
+        loop {
+            {
+                let pending = self.batch.lock();
+                if pending.closed {
+                    return true;
+                }
+                if !pending.events.is_empty() {
+                    break;
+                }
+            }
+            self.batch.changed.notified().await;
+        }
+
+        let deadline = Instant::now().checked_add(self.interval);
+        loop {
+            {
+                let pending = self.batch.lock();
+                if pending.closed {
+                    return true;
+                }
+                if pending.events.len() >= self.batch.size {
+                    return false;
+                }
+            }
+            match deadline {
+                Some(deadline) => if tokio::time::timeout_at(deadline, self.batch.changed.notified()).await.is_err() {
+                    return self.batch.lock().closed;
+                },
+                None => self.batch.changed.notified().await,
+            }
+        }
+    }
+
+    // One stream per level, as the level is a label
+    async fn push(&self, events: &[LokiEvent]) -> Result<(), reqwest::Error> {
+        let mut streams: Vec<(Level, Vec<Value>)> = Vec::new();
+        for event in events {
+            let value = json!([event.timestamp.to_string(), event.line]);
+            match streams.iter_mut().find(|(level, _)| *level == event.level) {
+                Some((_, values)) => values.push(value),
+                None => streams.push((event.level, vec![value])),
+            }
+        }
+        let streams = streams.into_iter()
+            .map(|(level, values)| {
+                let mut labels = self.labels.iter()
+                    .map(|(key, value)| (key.clone(), json!(value)))
+                    .collect::<Map<String, Value>>();
+                labels.insert("level".to_string(), json!(level.as_str().to_lowercase()));
+                json!({"stream": labels, "values": values})
+            })
+            .collect::<Vec<Value>>();
+
+        // The events of the HTTP client must not be logged, each push would produce more events
+        self.client.post(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(json!({"streams": streams}).to_string())
+            .send()
+            .with_subscriber(NoSubscriber::default())
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use axum::http::StatusCode;
+    use tracing_subscriber::layer::SubscriberExt;
+    use super::*;
+
+    type Pushes = Arc<Mutex<Vec<(HeaderMap, Value)>>>;
+
+    async fn loki_server() -> (Url, Pushes) {
+        let pushes = Pushes::default();
+        let received = Arc::clone(&pushes);
+        let router = axum::Router::new().route("/loki/api/v1/push", axum::routing::post(move |headers: HeaderMap, body: String| async move {
+            received.lock().unwrap().push((headers, serde_json::from_str(&body).unwrap()));
+            StatusCode::NO_CONTENT
+        }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        (url, pushes)
+    }
+
+    fn pushed_lines(pushes: &Pushes) -> Vec<Value> {
+        pushes.lock().unwrap().iter()
+            .flat_map(|(_, push)| push["streams"].as_array().unwrap().clone())
+            .flat_map(|stream| stream["values"].as_array().unwrap().clone())
+            .map(|value| serde_json::from_str(value[1].as_str().unwrap()).unwrap())
+            .collect()
+    }
+
+    async fn wait_for_lines(pushes: &Pushes, count: usize) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pushed_lines(pushes).len() < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("the events were not pushed");
+    }
+
+    #[test]
+    fn test_builder() {
+        assert!(builder().label("service", "agenda").is_ok());
+        assert!(builder().label("service.name", "agenda").is_err());
+        assert!(builder().label("1service", "agenda").is_err());
+        assert!(builder().label("level", "info").is_err());
+        assert!(builder().label("env", "a").unwrap().label("env", "b").is_err());
+        assert!(builder().http_header("X-Scope-OrgID", "tenant\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_loki_batch_size() {
+        let (url, pushes) = loki_server().await;
+        let (layer, controller, task) = builder()
+            .label("service", "agenda").unwrap()
+            .extra_field("region", "eu")
+            .http_header("X-Scope-OrgID", "tenant").unwrap()
+            .batch_size(100)
+            .batch_interval(Duration::from_secs(60))
+            .build_url(url)
+            .unwrap();
+        let task = tokio::spawn(task.run());
+
+        // Far more events than the interval would let through, they are pushed as the batches fill up
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            let _span = tracing::info_span!("export", format = "csv").entered();
+            for index in 0..2000 {
+                tracing::info!(index, "event");
+            }
+        });
+        wait_for_lines(&pushes, 2000).await;
+
+        let lines = pushed_lines(&pushes);
+        assert_eq!(lines.iter().map(|line| line["index"].as_i64().unwrap()).collect::<HashSet<i64>>().len(), 2000);
+        assert_eq!(lines[0]["message"], "event");
+        assert_eq!(lines[0]["format"], "csv");
+        assert_eq!(lines[0]["region"], "eu");
+        assert_eq!(lines[0]["_spans"], json!(["export"]));
+        let (headers, push) = pushes.lock().unwrap()[0].clone();
+        assert_eq!(headers.get("x-scope-orgid").unwrap(), "tenant");
+        assert_eq!(push["streams"][0]["stream"], json!({"service": "agenda", "level": "info"}));
+
+        controller.shutdown();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_loki_batch_interval() {
+        let (url, pushes) = loki_server().await;
+        let (layer, controller, task) = builder()
+            .batch_interval(Duration::from_millis(50))
+            .build_url(url)
+            .unwrap();
+        let task = tokio::spawn(task.run());
+        let subscriber = tracing_subscriber::registry().with(layer);
+        let dispatch = tracing::Dispatch::new(subscriber);
+
+        // A batch far from full is pushed once its interval elapsed
+        tracing::dispatcher::with_default(&dispatch, || tracing::warn!("first"));
+        wait_for_lines(&pushes, 1).await;
+        assert_eq!(pushes.lock().unwrap()[0].1["streams"][0]["stream"]["level"], "warn");
+
+        // What is left is pushed on shutdown
+        tracing::dispatcher::with_default(&dispatch, || tracing::info!("last"));
+        controller.shutdown();
+        task.await.unwrap();
+        assert_eq!(pushed_lines(&pushes).len(), 2);
+    }
+}
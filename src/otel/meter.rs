@@ -0,0 +1,211 @@
+use once_cell::sync::{Lazy, OnceCell};
+use opentelemetry::{global, KeyValue};
+use opentelemetry::metrics::{Counter, Histogram, MetricsError};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::runtime;
+use opentelemetry_stdout as stdout;
+use tonic::Code;
+use crate::database::error::DatabaseError;
+use crate::otel::resource::RESOURCE;
+
+
+// Kept so the provider can be flushed and shut down, the global API does not expose it
+static METER_PROVIDER: OnceCell<SdkMeterProvider> = OnceCell::new();
+
+// Instruments are created on first use, so the meter provider must be set before that
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+
+pub struct Metrics {
+    rpc_requests: Counter<u64>,
+    rpc_duration: Histogram<f64>,
+    rpc_throttled: Counter<u64>,
+    db_errors: Counter<u64>,
+    db_connection_wait_time: Histogram<f64>,
+    db_cache_hits: Counter<u64>,
+    db_cache_misses: Counter<u64>,
+}
+
+
+impl Metrics {
+    fn new() -> Self {
+        let meter = global::meter("tonic-server");
+        Metrics {
+            rpc_requests: meter.u64_counter("rpc.server.requests")
+                .with_description("Number of RPCs handled by the server")
+                .with_unit("{request}")
+                .init(),
+            rpc_duration: meter.f64_histogram("rpc.server.duration")
+                .with_description("Duration of the RPCs handled by the server")
+                .with_unit("ms")
+                .init(),
+            rpc_throttled: meter.u64_counter("rpc.server.throttled")
+                .with_description("Number of RPCs rejected by the rate limiter")
+                .with_unit("{request}")
+                .init(),
+            db_errors: meter.u64_counter("db.client.errors")
+                .with_description("Number of errors returned by the database layer")
+                .with_unit("{error}")
+                .init(),
+            db_connection_wait_time: meter.f64_histogram("db.client.connections.wait_time")
+                .with_description("Time it took to obtain a connection from the pool")
+                .with_unit("ms")
+                .init(),
+            db_cache_hits: meter.u64_counter("db.client.cache.hits")
+                .with_description("Number of reads served by the database cache")
+                .with_unit("{read}")
+                .init(),
+            db_cache_misses: meter.u64_counter("db.client.cache.misses")
+                .with_description("Number of cacheable reads that went to the database")
+                .with_unit("{read}")
+                .init(),
+        }
+    }
+
+    pub fn record_rpc(&self, service: &str, method: &str, code: Code, duration_ms: f64) {
+        let attributes = [
+            KeyValue::new("rpc.system", "grpc"),
+            KeyValue::new("rpc.service", service.to_string()),
+            KeyValue::new("rpc.method", method.to_string()),
+            KeyValue::new("rpc.grpc.status_code", code as i64),
+        ];
+        self.rpc_requests.add(1, &attributes);
+        self.rpc_duration.record(duration_ms, &attributes);
+    }
+
+    pub fn record_throttled(&self, service: &str, method: &str, limit: &'static str) {
+        self.rpc_throttled.add(1, &[
+            KeyValue::new("rpc.service", service.to_string()),
+            KeyValue::new("rpc.method", method.to_string()),
+            KeyValue::new("limit", limit),
+        ]);
+    }
+
+    pub fn record_database_error(&self, error: &DatabaseError) {
+        self.db_errors.add(1, &[KeyValue::new("error.type", error.kind())]);
+    }
+
+    pub fn record_connection_wait_time(&self, pool_name: &str, wait_ms: f64) {
+        self.db_connection_wait_time.record(wait_ms, &[KeyValue::new("pool.name", pool_name.to_string())]);
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        match hit {
+            true => self.db_cache_hits.add(1, &[]),
+            false => self.db_cache_misses.add(1, &[]),
+        }
+    }
+}
+
+
+pub struct PoolUsage {
+    pub size: u64,
+    pub idle: u64,
+    pub max: u64,
+}
+
+
+// Registers the gauges of a connection pool, the callback is read on every collection
+pub fn register_pool_gauges<F>(pool_name: &str, usage: F)
+where
+    F: Fn() -> PoolUsage + Send + Sync + 'static,
+{
+    let meter = global::meter("tonic-server");
+    let usage = std::sync::Arc::new(usage);
+    let max_usage = usage.clone();
+    let pool_name = pool_name.to_string();
+    let max_pool_name = pool_name.clone();
+
+    meter.u64_observable_gauge("db.client.connections.usage")
+        .with_description("Number of connections that are currently in the state described by the state attribute")
+        .with_unit("{connection}")
+        .with_callback(move |observer| {
+            let usage = usage();
+            observer.observe(usage.idle, &[KeyValue::new("pool.name", pool_name.clone()), KeyValue::new("state", "idle")]);
+            observer.observe(usage.size.saturating_sub(usage.idle), &[KeyValue::new("pool.name", pool_name.clone()), KeyValue::new("state", "used")]);
+        })
+        .init();
+
+    meter.u64_observable_gauge("db.client.connections.max")
+        .with_description("Maximum number of open connections allowed")
+        .with_unit("{connection}")
+        .with_callback(move |observer| {
+            observer.observe(max_usage().max, &[KeyValue::new("pool.name", max_pool_name.clone())]);
+        })
+        .init();
+}
+
+
+// Where metrics are sent, None when they are written to stdout
+pub fn metrics_endpoint() -> Option<String> {
+    match std::env::var("OTEL_EXPORTER_METRICS").unwrap_or("stdout".to_string()).as_str() {
+        "otlp" => Some(std::env::var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT").unwrap_or("http://localhost:4317".to_string())),
+        _ => None,
+    }
+}
+
+fn init_otlp_meter_provider() -> Result<SdkMeterProvider, MetricsError> {
+    opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(metrics_endpoint().unwrap_or_default()),
+        )
+        .with_resource(RESOURCE.clone())
+        .build()
+}
+
+fn init_sdk_meter_provider() -> Result<SdkMeterProvider, MetricsError> {
+    let reader = PeriodicReader::builder(stdout::MetricsExporter::default(), runtime::Tokio).build();
+    Ok(SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(RESOURCE.clone())
+        .build())
+}
+
+pub fn get_meter_provider() -> Result<SdkMeterProvider, MetricsError> {
+    let meter_type = std::env::var("OTEL_EXPORTER_METRICS").unwrap_or("stdout".to_string());
+
+    match meter_type.as_str() {
+        "otlp" => init_otlp_meter_provider(),
+        _ => init_sdk_meter_provider(),
+    }
+}
+
+pub fn set_meter_provider(provider: SdkMeterProvider) {
+    global::set_meter_provider(provider.clone());
+    let _ = METER_PROVIDER.set(provider);
+}
+
+pub fn shutdown_meter_provider() -> Result<(), MetricsError> {
+    match METER_PROVIDER.get() {
+        Some(provider) => provider.shutdown(),
+        None => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The periodic reader blocks on shutdown, it needs another worker to make progress
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_meter_provider() {
+        let result = get_meter_provider();
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_record_metrics() {
+        METRICS.record_rpc("agenda.v1.AgendaService", "Ping", Code::Ok, 1.0);
+        METRICS.record_database_error(&DatabaseError::ConnectionError);
+        METRICS.record_connection_wait_time("primary", 1.0);
+        METRICS.record_cache_lookup(true);
+        METRICS.record_throttled("agenda.v1.AgendaService", "GetAgendas", "global");
+        register_pool_gauges("primary", || PoolUsage {size: 2, idle: 1, max: 10});
+    }
+}
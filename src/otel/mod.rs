@@ -1,13 +1,29 @@
 mod tracer;
 mod logger;
+mod json;
+mod loki;
+mod meter;
+mod propagator;
+mod resource;
+mod sampler;
 
 use std::error::Error;
 use opentelemetry::trace::TracerProvider;
-use crate::otel::logger::{get_logger, set_logger};
-use crate::otel::tracer::get_tracer_provider;
+use crate::otel::logger::{get_logger, logs_endpoint, set_logger, shutdown_logger};
+use crate::otel::meter::{get_meter_provider, metrics_endpoint, set_meter_provider, shutdown_meter_provider};
+use crate::otel::propagator::{get_propagator, set_propagator};
+use crate::otel::tracer::{get_tracer_provider, traces_endpoint};
+use crate::shutdown::report_step;
+
+pub use crate::otel::logger::{get_log_filter, reload_log_filter};
+pub use crate::otel::meter::{register_pool_gauges, PoolUsage, METRICS};
 
 pub fn init_tracer_and_logger() -> Result<(), Box<dyn Error>> {
     let exporter = get_tracer_provider()?;
+
+    set_meter_provider(get_meter_provider()?);
+
+    set_propagator(get_propagator());
     
     let log_layer = get_logger()?;
 
@@ -18,8 +34,25 @@ pub fn init_tracer_and_logger() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub fn stop_tracer_and_logger() {
-    opentelemetry::global::shutdown_tracer_provider();
+// Endpoints of the exporters which send telemetry over the network, by signal
+pub fn exporter_endpoints() -> Vec<(&'static str, String)> {
+    [("traces", traces_endpoint()), ("metrics", metrics_endpoint()), ("logs", logs_endpoint())]
+        .into_iter()
+        .filter_map(|(signal, endpoint)| endpoint.map(|endpoint| (signal, endpoint)))
+        .collect()
+}
+
+// Logs go last so the outcome of the other steps is still exported.
+// The providers block until their exporters are done, so they are shut down off the runtime workers.
+pub async fn stop_tracer_and_logger() {
+    report_step("flush traces", async {
+        tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await
+    }).await;
+    report_step("flush metrics", async {
+        tokio::task::spawn_blocking(shutdown_meter_provider).await.map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())
+    }).await;
+    report_step("flush logs", shutdown_logger()).await;
 }
 
 
@@ -28,8 +61,22 @@ mod tests {
     use tracing::{debug, error, info, span, warn, Level};
     use super::*;
 
-    #[test]
-    fn test_tracer_and_logger() {
+    #[tokio::test]
+    async fn test_exporter_endpoints() {
+        assert!(exporter_endpoints().is_empty());
+
+        std::env::set_var("OTEL_EXPORTER_TRACES", "otlp");
+        std::env::set_var("OTEL_EXPORTER_LOGS", "loki");
+        std::env::set_var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT", "http://loki:3100");
+        assert_eq!(exporter_endpoints(), vec![("traces", "http://localhost:4317".to_string()), ("logs", "http://loki:3100".to_string())]);
+
+        std::env::remove_var("OTEL_EXPORTER_OTLP_LOGS_ENDPOINT");
+        std::env::remove_var("OTEL_EXPORTER_LOGS");
+        std::env::remove_var("OTEL_EXPORTER_TRACES");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_tracer_and_logger() {
         let result = init_tracer_and_logger();
 
         assert!(result.is_ok());
@@ -42,6 +89,6 @@ mod tests {
 
         my_span.exit();
 
-        stop_tracer_and_logger();
+        stop_tracer_and_logger().await;
     }
 }
@@ -0,0 +1,62 @@
+use opentelemetry::global;
+use opentelemetry::propagation::{TextMapCompositePropagator, TextMapPropagator};
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use opentelemetry_zipkin::{B3Encoding, Propagator as B3Propagator};
+
+
+fn propagator_from_name(name: &str) -> Option<Box<dyn TextMapPropagator + Send + Sync>> {
+    match name.trim() {
+        "tracecontext" => Some(Box::new(TraceContextPropagator::new())),
+        "baggage" => Some(Box::new(BaggagePropagator::new())),
+        "b3" => Some(Box::new(B3Propagator::with_encoding(B3Encoding::SingleHeader))),
+        "b3multi" => Some(Box::new(B3Propagator::with_encoding(B3Encoding::MultipleHeader))),
+        _ => None,
+    }
+}
+
+// Builds the propagator from the comma separated list in OTEL_PROPAGATORS, unknown names are ignored
+pub fn get_propagator() -> TextMapCompositePropagator {
+    let names = std::env::var("OTEL_PROPAGATORS").unwrap_or("tracecontext,baggage".to_string());
+
+    TextMapCompositePropagator::new(
+        names.split(',').filter_map(propagator_from_name).collect()
+    )
+}
+
+pub fn set_propagator(propagator: TextMapCompositePropagator) {
+    global::set_text_map_propagator(propagator);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use opentelemetry::trace::TraceContextExt;
+    use super::*;
+
+    #[test]
+    fn test_propagator_tracecontext() {
+        let propagator = get_propagator();
+        let carrier = HashMap::from([
+            ("traceparent".to_string(), "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string()),
+        ]);
+
+        let context = propagator.extract(&carrier);
+
+        assert_eq!(context.span().span_context().trace_id().to_string(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn test_propagator_b3() {
+        std::env::set_var("OTEL_PROPAGATORS", "tracecontext, b3, unknown");
+        let propagator = get_propagator();
+        let carrier = HashMap::from([
+            ("b3".to_string(), "4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1".to_string()),
+        ]);
+
+        let context = propagator.extract(&carrier);
+
+        assert_eq!(context.span().span_context().span_id().to_string(), "00f067aa0ba902b7");
+        std::env::remove_var("OTEL_PROPAGATORS");
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::resource::{EnvResourceDetector, TelemetryResourceDetector};
+use opentelemetry_semantic_conventions::resource;
+
+
+// Shared by the tracer, logger and meter providers so every signal is labeled the same way
+pub static RESOURCE: Lazy<Resource> = Lazy::new(detect_resource);
+
+// Resource attributes that are also used as Loki labels, the rest would make streams too granular
+const LOKI_LABEL_KEYS: [&str; 5] = [
+    resource::SERVICE_NAME,
+    resource::SERVICE_NAMESPACE,
+    resource::SERVICE_VERSION,
+    resource::DEPLOYMENT_ENVIRONMENT,
+    resource::HOST_NAME,
+];
+
+
+fn service_attributes() -> Vec<KeyValue> {
+    vec![
+        KeyValue::new(resource::SERVICE_NAME, "tonic-server"),
+        KeyValue::new(resource::SERVICE_VERSION, env!("CARGO_PKG_VERSION")),
+        KeyValue::new(resource::SERVICE_INSTANCE_ID, uuid::Uuid::new_v4().to_string()),
+    ]
+}
+
+fn host_attributes() -> Vec<KeyValue> {
+    let host_name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty());
+
+    host_name
+        .map(|name| vec![KeyValue::new(resource::HOST_NAME, name)])
+        .unwrap_or_default()
+}
+
+fn process_attributes() -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new(resource::PROCESS_PID, std::process::id() as i64),
+        KeyValue::new(resource::PROCESS_RUNTIME_NAME, "rustc"),
+        KeyValue::new(resource::PROCESS_RUNTIME_VERSION, env!("RUSTC_VERSION")),
+    ];
+    if let Ok(executable) = std::env::current_exe() {
+        attributes.push(KeyValue::new(resource::PROCESS_EXECUTABLE_PATH, executable.display().to_string()));
+    }
+    attributes
+}
+
+fn container_attributes() -> Vec<KeyValue> {
+    let from_cgroup = std::fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|content| container_id_from_cgroup(&content));
+    let from_mountinfo = || std::fs::read_to_string("/proc/self/mountinfo")
+        .ok()
+        .and_then(|content| container_id_from_mountinfo(&content));
+
+    from_cgroup.or_else(from_mountinfo)
+        .map(|id| vec![KeyValue::new(resource::CONTAINER_ID, id)])
+        .unwrap_or_default()
+}
+
+
+fn is_container_id(candidate: &str) -> bool {
+    candidate.len() == 64 && candidate.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Works for cgroup v1 ("12:memory:/docker/<id>") and for systemd scopes ("0::/system.slice/docker-<id>.scope")
+pub fn container_id_from_cgroup(content: &str) -> Option<String> {
+    content.lines()
+        .filter_map(|line| line.rsplit(':').next())
+        .filter_map(|path| path.rsplit('/').next())
+        .map(|segment| {
+            let segment = segment.trim_end_matches(".scope");
+            segment.rsplit('-').next().unwrap_or(segment)
+        })
+        .find(|candidate| is_container_id(candidate))
+        .map(|id| id.to_string())
+}
+
+// With cgroup v2 and a private cgroup namespace the id is only visible in the container mounts
+pub fn container_id_from_mountinfo(content: &str) -> Option<String> {
+    content.lines()
+        .flat_map(|line| line.split_whitespace())
+        .find_map(|path| {
+            let segments: Vec<&str> = path.split('/').collect();
+            segments.windows(2)
+                .find(|pair| pair[0] == "containers" && is_container_id(pair[1]))
+                .map(|pair| pair[1].to_string())
+        })
+}
+
+
+// Detected attributes come first, then OTEL_RESOURCE_ATTRIBUTES, then OTEL_SERVICE_NAME
+fn detect_resource() -> Resource {
+    let detected = Resource::new(
+        [service_attributes(), host_attributes(), process_attributes(), container_attributes()].concat()
+    );
+    let from_env = Resource::from_detectors(
+        Duration::from_secs(0),
+        vec![Box::new(TelemetryResourceDetector), Box::new(EnvResourceDetector::new())],
+    );
+    let resource = detected.merge(&from_env);
+
+    match std::env::var("OTEL_SERVICE_NAME") {
+        Ok(service_name) if !service_name.is_empty() => resource.merge(
+            &Resource::new(vec![KeyValue::new(resource::SERVICE_NAME, service_name)])
+        ),
+        _ => resource,
+    }
+}
+
+
+// Loki label names cannot contain dots, so "service.name" becomes "service_name"
+pub fn loki_labels(resource: &Resource) -> Vec<(String, String)> {
+    resource.iter()
+        .filter(|(key, _)| LOKI_LABEL_KEYS.contains(&key.as_str()))
+        .map(|(key, value)| (key.as_str().replace('.', "_"), value.to_string()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::Key;
+    use super::*;
+
+    #[test]
+    fn test_container_id_from_cgroup_v1() {
+        let content = "12:memory:/docker/3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2\n0::/";
+
+        assert_eq!(
+            container_id_from_cgroup(content),
+            Some("3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_container_id_from_cgroup_systemd() {
+        let content = "0::/system.slice/docker-3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2.scope";
+
+        assert_eq!(
+            container_id_from_cgroup(content),
+            Some("3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2".to_string())
+        );
+        assert_eq!(container_id_from_cgroup("0::/user.slice/session-2.scope"), None);
+    }
+
+    #[test]
+    fn test_container_id_from_mountinfo() {
+        let content = "736 720 0:48 /containers/3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2/hostname /etc/hostname rw - ext4 /dev/vda1 rw";
+
+        assert_eq!(
+            container_id_from_mountinfo(content),
+            Some("3c5e8dd3a5a7e6f4b2bd0d0d7f7a1b2c3d4e5f60718293a4b5c6d7e8f9a0b1c2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_detect_resource_overrides() {
+        std::env::set_var("OTEL_RESOURCE_ATTRIBUTES", "deployment.environment=test,service.name=from-attributes");
+        std::env::set_var("OTEL_SERVICE_NAME", "from-service-name");
+
+        let resource = detect_resource();
+
+        assert_eq!(resource.get(Key::new(resource::SERVICE_NAME)).map(|v| v.to_string()), Some("from-service-name".to_string()));
+        assert_eq!(resource.get(Key::new(resource::SERVICE_VERSION)).map(|v| v.to_string()), Some(env!("CARGO_PKG_VERSION").to_string()));
+        assert!(resource.get(Key::new(resource::PROCESS_PID)).is_some());
+
+        let labels = loki_labels(&resource);
+        assert!(labels.contains(&("deployment_environment".to_string(), "test".to_string())));
+        assert!(!labels.iter().any(|(key, _)| key == "process_pid"));
+
+        std::env::remove_var("OTEL_RESOURCE_ATTRIBUTES");
+        std::env::remove_var("OTEL_SERVICE_NAME");
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry::trace::{Link, SamplingDecision, SamplingResult, SpanKind, TraceContextExt, TraceId};
+use opentelemetry_sdk::trace::{Config, Sampler, ShouldSample};
+use crate::middleware::ratelimit::TokenBucket;
+
+
+// Samples at most `traces_per_second` new traces, with bursts of up to one second worth of traces
+#[derive(Debug, Clone)]
+pub struct RateLimitingSampler {
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimitingSampler {
+    pub fn new(traces_per_second: f64) -> Self {
+        RateLimitingSampler {
+            bucket: Arc::new(Mutex::new(TokenBucket::new(traces_per_second.max(1.0), traces_per_second))),
+        }
+    }
+}
+
+impl ShouldSample for RateLimitingSampler {
+    fn should_sample(
+        &self,
+        parent_context: Option<&Context>,
+        _trace_id: TraceId,
+        _name: &str,
+        _span_kind: &SpanKind,
+        _attributes: &[KeyValue],
+        _links: &[Link],
+    ) -> SamplingResult {
+        let sampled = self.bucket.lock()
+            .map(|mut bucket| bucket.try_acquire())
+            .unwrap_or(false);
+
+        SamplingResult {
+            decision: if sampled { SamplingDecision::RecordAndSample } else { SamplingDecision::Drop },
+            attributes: Vec::new(),
+            trace_state: parent_context
+                .map(|cx| cx.span().span_context().trace_state().clone())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+
+// Follows the OTEL_TRACES_SAMPLER and OTEL_TRACES_SAMPLER_ARG conventions, plus rate limited samplers
+// whose argument is the number of traces per second
+pub fn with_sampler(config: Config) -> Config {
+    let sampler_type = std::env::var("OTEL_TRACES_SAMPLER").unwrap_or("parentbased_always_on".to_string());
+    let sampler_arg = std::env::var("OTEL_TRACES_SAMPLER_ARG").ok().and_then(|arg| arg.parse::<f64>().ok());
+
+    match sampler_type.as_str() {
+        "always_on" => config.with_sampler(Sampler::AlwaysOn),
+        "always_off" => config.with_sampler(Sampler::AlwaysOff),
+        "traceidratio" => config.with_sampler(Sampler::TraceIdRatioBased(sampler_arg.unwrap_or(1.0))),
+        "ratelimited" => config.with_sampler(RateLimitingSampler::new(sampler_arg.unwrap_or(100.0))),
+        "parentbased_always_off" => config.with_sampler(Sampler::ParentBased(Box::new(Sampler::AlwaysOff))),
+        "parentbased_traceidratio" => config.with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(sampler_arg.unwrap_or(1.0))))),
+        "parentbased_ratelimited" => config.with_sampler(Sampler::ParentBased(Box::new(RateLimitingSampler::new(sampler_arg.unwrap_or(100.0))))),
+        _ => config.with_sampler(Sampler::ParentBased(Box::new(Sampler::AlwaysOn))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rate_limiting_sampler() {
+        let sampler = RateLimitingSampler::new(1.0);
+        let sample = || sampler.should_sample(None, TraceId::from_bytes([1; 16]), "span", &SpanKind::Server, &[], &[]).decision;
+
+        assert_eq!(sample(), SamplingDecision::RecordAndSample);
+        assert_eq!(sample(), SamplingDecision::Drop);
+    }
+
+    #[tokio::test]
+    async fn test_with_sampler_from_env() {
+        std::env::set_var("OTEL_TRACES_SAMPLER", "traceidratio");
+        std::env::set_var("OTEL_TRACES_SAMPLER_ARG", "0.25");
+
+        let config = with_sampler(Config::default());
+
+        assert_eq!(format!("{:?}", config.sampler), "TraceIdRatioBased(0.25)");
+
+        std::env::remove_var("OTEL_TRACES_SAMPLER");
+        std::env::remove_var("OTEL_TRACES_SAMPLER_ARG");
+    }
+}
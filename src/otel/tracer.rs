@@ -1,19 +1,20 @@
-use once_cell::sync::Lazy;
-use opentelemetry::KeyValue;
 use opentelemetry::trace::TraceError;
 use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::{runtime, Resource};
+use opentelemetry_sdk::runtime;
 use opentelemetry_sdk::trace::Config;
 use opentelemetry_sdk::trace::TracerProvider as SDKTracerProvider;
 use opentelemetry_stdout as stdout;
+use crate::otel::resource::RESOURCE;
+use crate::otel::sampler::with_sampler;
 
 
-static RESOURCE: Lazy<Resource> = Lazy::new(|| {
-    Resource::new(vec![KeyValue::new(
-        opentelemetry_semantic_conventions::resource::SERVICE_NAME,
-        "tonic-server",
-    )])
-});
+// Where spans are sent, None when they are written to stdout
+pub fn traces_endpoint() -> Option<String> {
+    match std::env::var("OTEL_EXPORTER_TRACES").unwrap_or("stdout".to_string()).as_str() {
+        "otlp" => Some(std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").unwrap_or("http://localhost:4317".to_string())),
+        _ => None,
+    }
+}
 
 fn init_otlp_tracer_provider() -> Result<SDKTracerProvider, TraceError> {
     opentelemetry_otlp::new_pipeline()
@@ -21,18 +22,16 @@ fn init_otlp_tracer_provider() -> Result<SDKTracerProvider, TraceError> {
         .with_exporter(
             opentelemetry_otlp::new_exporter()
                 .tonic()
-                .with_endpoint(
-                    std::env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
-                        .unwrap_or("http://localhost:4317".to_string())
-                ),
+                .with_endpoint(traces_endpoint().unwrap_or_default()),
         )
-        .with_trace_config(Config::default().with_resource(RESOURCE.clone()))
+        .with_trace_config(with_sampler(Config::default().with_resource(RESOURCE.clone())))
         .install_batch(runtime::Tokio)
 }
 
 fn init_sdk_tracer_provider() -> Result<SDKTracerProvider, TraceError> {
     Ok(SDKTracerProvider::builder()
         .with_simple_exporter(stdout::SpanExporter::default())
+        .with_config(with_sampler(Config::default().with_resource(RESOURCE.clone())))
         .build())
 }
 
@@ -0,0 +1,330 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tonic::{Code, Request, Response, Status};
+use tracing::instrument;
+use tracing_subscriber::EnvFilter;
+use crate::admin::{CreateApiKeyRequest, CreateApiKeyResponse, GetLogLevelRequest, GetLogLevelResponse, ListApiKeysRequest, ListApiKeysResponse, RevokeApiKeyRequest, RevokeApiKeyResponse, SetLogLevelRequest, SetLogLevelResponse};
+use crate::admin::admin_service_server::AdminService;
+use crate::auth::{format_api_key, generate_secret, SCOPES};
+use crate::database::database_object::DBLayers;
+use crate::model::ApiKeyModel;
+use crate::otel::{get_log_filter, reload_log_filter};
+use crate::trace_and_handle_error;
+
+
+// A temporary filter, the baseline is the filter that was active before the first temporary change.
+// The generation tells a timer whether its change is still the pending one.
+#[derive(Debug)]
+struct PendingRevert {
+    generation: u64,
+    baseline: String,
+    revert_at: SystemTime,
+    task: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+pub struct CustomAdminService {
+    pending_revert: Arc<Mutex<Option<PendingRevert>>>,
+    generations: AtomicU64,
+    database: Arc<DBLayers>,
+}
+
+
+impl CustomAdminService {
+    pub fn new(database: Arc<DBLayers>) -> Self {
+        CustomAdminService {
+            pending_revert: Arc::default(),
+            generations: AtomicU64::new(0),
+            database,
+        }
+    }
+}
+
+
+fn revert_log_filter_after(pending_revert: Arc<Mutex<Option<PendingRevert>>>, generation: u64, baseline: String, ttl: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        tokio::time::sleep(ttl).await;
+
+        // A newer change may have replaced this one after the timer fired, it is not reverted
+        let mut pending = pending_revert.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if pending.as_ref().is_none_or(|pending| pending.generation != generation) {
+            return;
+        }
+        pending.take();
+        let result = EnvFilter::try_new(&baseline)
+            .map_err(|err| err.to_string())
+            .and_then(|filter| reload_log_filter(filter).map_err(|err| err.to_string()));
+
+        match result {
+            Ok(_) => tracing::info!("Log filter reverted to {}", baseline),
+            Err(err) => tracing::error!("Error reverting the log filter to {}: {}", baseline, err),
+        }
+    })
+}
+
+
+#[tonic::async_trait]
+impl AdminService for CustomAdminService {
+    #[instrument(level = "info", target = "service::get_log_level", fields(success, error, warn))]
+    async fn get_log_level(
+        &self,
+        _request: Request<GetLogLevelRequest>,
+    ) -> Result<Response<GetLogLevelResponse>, Status> {
+        trace_and_handle_error!({
+            let filter = get_log_filter()
+                .ok_or(Status::new(Code::Unavailable, "the logger has not been initialized"))?;
+            let pending = self.pending_revert.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            Ok::<Response<GetLogLevelResponse>, Status>(Response::new(GetLogLevelResponse {
+                filter,
+                revert_at: pending.as_ref().map(|pending| pending.revert_at.into()),
+            }))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::set_log_level", fields(success, error, warn))]
+    async fn set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> Result<Response<SetLogLevelResponse>, Status> {
+        trace_and_handle_error!({
+            let message :SetLogLevelRequest = request.into_inner();
+            let filter = EnvFilter::try_new(&message.filter)
+                .map_err(|err| Status::new(Code::InvalidArgument, format!("invalid filter: {err}")))?;
+            let ttl = message.ttl
+                .map(Duration::try_from)
+                .transpose()
+                .map_err(|err| Status::new(Code::InvalidArgument, format!("invalid ttl: {err}")))?;
+            // Checked before anything changes, so a ttl out of range leaves the filter and its revert as they were
+            let revert_at = match ttl {
+                Some(ttl) => Some((ttl, SystemTime::now().checked_add(ttl)
+                    .ok_or_else(|| Status::new(Code::InvalidArgument, "invalid ttl: out of range"))?)),
+                None => None,
+            };
+
+            let mut pending = self.pending_revert.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let new_filter = filter.to_string();
+            let previous_filter = reload_log_filter(filter)
+                .map_err(|err| Status::new(Code::Internal, err.to_string()))?;
+
+            // A change without ttl becomes the new baseline, a change with ttl keeps the original one
+            let baseline = match pending.take() {
+                Some(previous_revert) => {
+                    previous_revert.task.abort();
+                    previous_revert.baseline
+                },
+                None => previous_filter.clone(),
+            };
+
+            let revert_at = revert_at.map(|(ttl, revert_at)| {
+                let generation = self.generations.fetch_add(1, Ordering::Relaxed) + 1;
+                *pending = Some(PendingRevert {
+                    generation,
+                    baseline: baseline.clone(),
+                    revert_at,
+                    task: revert_log_filter_after(self.pending_revert.clone(), generation, baseline, ttl),
+                });
+                revert_at
+            });
+
+            Ok::<Response<SetLogLevelResponse>, Status>(Response::new(SetLogLevelResponse {
+                filter: new_filter,
+                previous_filter,
+                revert_at: revert_at.map(|revert_at| revert_at.into()),
+            }))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::create_api_key", skip(request), fields(success, error, warn))]
+    async fn create_api_key(
+        &self,
+        request: Request<CreateApiKeyRequest>,
+    ) -> Result<Response<CreateApiKeyResponse>, Status> {
+        trace_and_handle_error!({
+            let message :CreateApiKeyRequest = request.into_inner();
+            if message.name.is_empty() {
+                return Err(Status::new(Code::InvalidArgument, "the name of the key is required"));
+            }
+            if message.scopes.is_empty() {
+                return Err(Status::new(Code::InvalidArgument, "at least one scope is required"));
+            }
+            if let Some(scope) = message.scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+                return Err(Status::new(Code::InvalidArgument, format!("unknown scope {scope}")));
+            }
+            let ttl = message.ttl
+                .map(Duration::try_from)
+                .transpose()
+                .map_err(|err| Status::new(Code::InvalidArgument, format!("invalid ttl: {err}")))?;
+            let expires_at = match ttl {
+                Some(ttl) => Some(SystemTime::now().checked_add(ttl)
+                    .and_then(|expires_at| expires_at.duration_since(UNIX_EPOCH).ok())
+                    .and_then(|expires_at| i64::try_from(expires_at.as_micros()).ok())
+                    .ok_or_else(|| Status::new(Code::InvalidArgument, "invalid ttl: out of range"))?),
+                None => None,
+            };
+
+            let (secret, secret_hash) = tokio::task::spawn_blocking(generate_secret)
+                .await
+                .map_err(|err| Status::new(Code::Internal, err.to_string()))?
+                .map_err(|err| Status::new(Code::Internal, err.to_string()))?;
+            let api_key = self.database.get_db_handler()
+                .create_api_key(ApiKeyModel::new(message.name, secret_hash, message.scopes, expires_at))
+                .await?;
+            tracing::info!("Created API key {} ({})", api_key.id, api_key.name);
+
+            Ok::<Response<CreateApiKeyResponse>, Status>(Response::new(CreateApiKeyResponse {
+                key: format_api_key(api_key.id, &secret),
+                api_key: Some(api_key.to_proto()),
+            }))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::list_api_keys", fields(success, error, warn))]
+    async fn list_api_keys(
+        &self,
+        request: Request<ListApiKeysRequest>,
+    ) -> Result<Response<ListApiKeysResponse>, Status> {
+        trace_and_handle_error!({
+            let api_keys = self.database.get_db_handler()
+                .retrieve_api_keys(request.into_inner().include_revoked)
+                .await?;
+
+            Ok::<Response<ListApiKeysResponse>, Status>(Response::new(ListApiKeysResponse {
+                api_keys: api_keys.iter().map(|api_key| api_key.to_proto()).collect(),
+            }))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::revoke_api_key", fields(success, error, warn))]
+    async fn revoke_api_key(
+        &self,
+        request: Request<RevokeApiKeyRequest>,
+    ) -> Result<Response<RevokeApiKeyResponse>, Status> {
+        trace_and_handle_error!({
+            let api_key = self.database.get_db_handler()
+                .revoke_api_key(request.into_inner().id)
+                .await?;
+            tracing::info!("Revoked API key {} ({})", api_key.id, api_key.name);
+
+            Ok::<Response<RevokeApiKeyResponse>, Status>(Response::new(RevokeApiKeyResponse {
+                api_key: Some(api_key.to_proto()),
+            }))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{parse_api_key, SCOPE_READ};
+    use super::*;
+
+    async fn service() -> CustomAdminService {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let database = DBLayers::new_db_handler().await.unwrap();
+        database.get_db_handler().init_database().await.unwrap();
+        CustomAdminService::new(Arc::new(database))
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level_invalid_filter() {
+        let service = service().await;
+
+        let result = service.set_log_level(Request::new(SetLogLevelRequest {
+            filter: "tonic_server=notalevel".to_string(),
+            ttl: None,
+        })).await;
+
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_set_log_level_invalid_ttl() {
+        let service = service().await;
+
+        let result = service.set_log_level(Request::new(SetLogLevelRequest {
+            filter: "info".to_string(),
+            ttl: Some(prost_types::Duration {seconds: -1, nanos: 0}),
+        })).await;
+
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+
+        let result = service.set_log_level(Request::new(SetLogLevelRequest {
+            filter: "info".to_string(),
+            ttl: Some(prost_types::Duration {seconds: i64::MAX, nanos: 0}),
+        })).await;
+
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_stale_revert() {
+        let pending_revert = Arc::new(Mutex::new(Some(PendingRevert {
+            generation: 2,
+            baseline: "info".to_string(),
+            revert_at: SystemTime::now(),
+            task: tokio::spawn(async {}),
+        })));
+
+        // The timer of the first change fires after the second one replaced it
+        revert_log_filter_after(pending_revert.clone(), 1, "info".to_string(), Duration::ZERO).await.unwrap();
+
+        let pending = pending_revert.lock().unwrap();
+        assert_eq!(pending.as_ref().map(|pending| pending.generation), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_api_key_lifecycle() {
+        let service = service().await;
+
+        let created = service.create_api_key(Request::new(CreateApiKeyRequest {
+            name: "ci".to_string(),
+            scopes: vec![SCOPE_READ.to_string()],
+            ttl: Some(prost_types::Duration {seconds: 3600, nanos: 0}),
+        })).await.unwrap().into_inner();
+        let api_key = created.api_key.unwrap();
+        assert_eq!(parse_api_key(&created.key).map(|(id, _)| id), Some(api_key.id));
+        assert!(api_key.expires_at.is_some());
+
+        let listed = service.list_api_keys(Request::new(ListApiKeysRequest {include_revoked: false})).await.unwrap().into_inner();
+        assert!(listed.api_keys.iter().any(|listed| listed.id == api_key.id));
+
+        let revoked = service.revoke_api_key(Request::new(RevokeApiKeyRequest {id: api_key.id})).await.unwrap().into_inner();
+        assert!(revoked.api_key.unwrap().revoked_at.is_some());
+
+        let listed = service.list_api_keys(Request::new(ListApiKeysRequest {include_revoked: false})).await.unwrap().into_inner();
+        assert!(!listed.api_keys.iter().any(|listed| listed.id == api_key.id));
+        assert_eq!(service.revoke_api_key(Request::new(RevokeApiKeyRequest {id: -1})).await.unwrap_err().code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_create_api_key_invalid_ttl() {
+        let service = service().await;
+
+        // Out of range for the system time, then for the microseconds stored in the database
+        for seconds in [i64::MAX, 10_000_000_000_000] {
+            let result = service.create_api_key(Request::new(CreateApiKeyRequest {
+                name: "ci".to_string(),
+                scopes: vec![SCOPE_READ.to_string()],
+                ttl: Some(prost_types::Duration {seconds, nanos: 0}),
+            })).await;
+
+            assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_api_key_invalid_scope() {
+        let service = service().await;
+
+        let result = service.create_api_key(Request::new(CreateApiKeyRequest {
+            name: "ci".to_string(),
+            scopes: vec!["agendas:everything".to_string()],
+            ttl: None,
+        })).await;
+
+        assert_eq!(result.unwrap_err().code(), Code::InvalidArgument);
+    }
+}
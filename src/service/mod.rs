@@ -1,11 +1,28 @@
+pub mod admin;
+
+use std::error::Error;
 use std::sync::Arc;
 use tracing::instrument;
-use tonic::{Request, Response, Status, Code};
-use crate::agenda::{PingRequest, PingResponse, CreateAgendaRequest, CreateAgendaResponse, GetAgendaRequest, GetAgendaResponse, UpdateAgendaRequest, UpdateAgendaResponse, DeleteAgendaRequest, DeleteAgendaResponse, GetAgendasRequest, GetAgendasResponse};
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Code, Streaming};
+use crate::agenda::{PingRequest, PingResponse, CreateAgendaRequest, CreateAgendaResponse, GetAgendaRequest, GetAgendaResponse, UpdateAgendaRequest, UpdateAgendaResponse, DeleteAgendaRequest, DeleteAgendaResponse, GetAgendasRequest, GetAgendasResponse, ListAgendaHistoryRequest, ListAgendaHistoryResponse, RestoreAgendaRequest, RestoreAgendaResponse, ExportAgendasRequest, FileChunk, ImportAgendasRequest, ImportAgendasResponse, ImportError};
 use crate::agenda::agenda_service_server::{AgendaService};
+use crate::audit::actor_from_request;
 use crate::database::database_object::DBLayers;
-use crate::model::AgendaModel;
-use crate::database::Database;
+use crate::database::deadline::{self, with_deadline};
+use crate::database::routing::{read_from_replica, with_read_from_replica};
+use crate::format::{read_agendas, write_agendas, FileFormat};
+use crate::middleware::limits::ConcurrencyPermit;
+use crate::model::{AgendaModel, AuditModel, timestamp_to_micros};
+
+// Exports read this many agendas at a time and send them in chunks of at most this many bytes
+const EXPORT_PAGE_SIZE: i64 = 500;
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+// Chunks waiting for a slow client, the export stops reading the database while they are not sent
+const EXPORT_BUFFERED_CHUNKS: usize = 4;
+// Imported files are decoded as a whole, so their size is bounded
+const IMPORT_MAX_BYTES: usize = 16 * 1024 * 1024;
 
 #[derive(Debug)]
 pub struct CustomAgendaService {
@@ -14,15 +31,16 @@ pub struct CustomAgendaService {
 
 
 impl CustomAgendaService {
-    pub async fn new() -> Self {
-        let database = DBLayers::new_db_handler().await.unwrap();
-        database.get_db_handler().init_database().await.unwrap();
-        CustomAgendaService {
-            database: Arc::new(DBLayers::new_db_handler().await.unwrap()),
-        }
+    pub async fn new() -> Result<Self, Box<dyn Error>> {
+        let database = DBLayers::new_db_handler().await?;
+        database.get_db_handler().init_database().await?;
+        Ok(CustomAgendaService {
+            database: Arc::new(database),
+        })
     }
 }
 
+#[macro_export]
 macro_rules! trace_and_handle_error {
     // Match the block of code within the macro invocation
     ({ $($code:tt)* }) => {{
@@ -40,18 +58,18 @@ macro_rules! trace_and_handle_error {
         // Match on the result to handle success and error cases
         match &result {
             Ok(_) => {
-                tracing::info!("Function executed successfully");
                 current_span.record("success", &true);
+                tracing::info!("Function executed successfully");
 
             },
             
             Err(err) if err.code() == Code::Internal => {
-                tracing::error!("Error in function: {}", err.to_string());
                 current_span.record("error", &format!("{}", err.to_string()));
+                tracing::error!("Error in function: {}", err.to_string());
             },
             Err(err) => {
-                tracing::warn!("Error in function: {}", err.to_string());
                 current_span.record("warn", &format!("{}", err.to_string()));
+                tracing::warn!("Error in function: {}", err.to_string());
             }
         }
         result
@@ -61,7 +79,7 @@ macro_rules! trace_and_handle_error {
 
 #[tonic::async_trait]
 impl AgendaService for CustomAgendaService {
-    #[instrument(level = "info", target = "service::ping")]
+    #[instrument(level = "info", target = "service::ping", fields(success, error, warn))]
     async fn ping(
         &self,
         _request: Request<PingRequest>,
@@ -75,15 +93,17 @@ impl AgendaService for CustomAgendaService {
         })
     }
 
-    #[instrument(level = "info", target = "service::create_agenda")]
+    #[instrument(level = "info", target = "service::create_agenda", fields(success, error, warn))]
     async fn create_agenda(
         &self,
         request: Request<CreateAgendaRequest>,
     ) -> Result<Response<CreateAgendaResponse>, Status> {
         trace_and_handle_error!({
-            let new_agenda: AgendaModel = Arc::clone(&self.database)
+            let actor = actor_from_request(&request);
+            let database = Arc::clone(&self.database);
+            let new_agenda: AgendaModel = database
                 .get_db_handler()
-                .create_agenda(AgendaModel::from_proto(request.into_inner().agenda)?)
+                .create_agenda(AgendaModel::from_proto(request.into_inner().agenda)?, &actor, "CreateAgenda")
                 .await?;
     
             Ok::<Response<CreateAgendaResponse>, Status>(Response::new(CreateAgendaResponse {
@@ -92,16 +112,24 @@ impl AgendaService for CustomAgendaService {
         })
     }
 
-    #[instrument(level = "info", target = "service::get_agenda")]
+    #[instrument(level = "info", target = "service::get_agenda", fields(success, error, warn))]
     async fn get_agenda(
         &self,
         request: Request<GetAgendaRequest>,
     ) -> Result<Response<GetAgendaResponse>, Status> {
         trace_and_handle_error!({
-            let new_agenda: AgendaModel = Arc::clone(&self.database)
-                .get_db_handler()
-                .retrieve_from_id(request.into_inner().id)
-                .await?;
+            let message :GetAgendaRequest = request.into_inner();
+            let database = Arc::clone(&self.database);
+            let new_agenda: AgendaModel = match message.as_of {
+                Some(as_of) => database
+                    .get_db_handler()
+                    .retrieve_from_id_as_of(message.id, timestamp_to_micros(&as_of)?)
+                    .await?,
+                None => database
+                    .get_db_handler()
+                    .retrieve_from_id(message.id)
+                    .await?,
+            };
     
             Ok::<Response<GetAgendaResponse>, Status>(Response::new(GetAgendaResponse {
                 agenda: Some(new_agenda.to_proto())
@@ -109,13 +137,13 @@ impl AgendaService for CustomAgendaService {
         })
     }
 
-    #[instrument(level = "info", target = "service::get_agendas")]
+    #[instrument(level = "info", target = "service::get_agendas", fields(success, error, warn))]
     async fn get_agendas(
         &self,
         request: Request<GetAgendasRequest>
     ) -> Result<Response<GetAgendasResponse>, Status> {
         trace_and_handle_error!({
-            let message :GetAgendasRequest = request.into_inner().clone();
+            let message :GetAgendasRequest = request.into_inner();
             let (agendas, page, items) = Arc::clone(&self.database)
                 .get_db_handler()
                 .retrieve_all(message.page, message.items)
@@ -129,16 +157,20 @@ impl AgendaService for CustomAgendaService {
         })
     }
 
-    #[instrument(level = "info", target = "service::update_agenda")]
+    #[instrument(level = "info", target = "service::update_agenda", fields(success, error, warn))]
     async fn update_agenda(
         &self,
         request: Request<UpdateAgendaRequest>,
     ) -> Result<Response<UpdateAgendaResponse>, Status> {
         trace_and_handle_error!({
-            let message :UpdateAgendaRequest = request.into_inner().clone();
-            let new_agenda: AgendaModel = Arc::clone(&self.database)
+            let actor = actor_from_request(&request);
+            let message :UpdateAgendaRequest = request.into_inner();
+            let database = Arc::clone(&self.database);
+            let agenda = AgendaModel::from_proto(message.agenda)?;
+            let paths = message.update_mask.map(|mask| mask.paths).unwrap_or_default();
+            let new_agenda: AgendaModel = database
                 .get_db_handler()
-                .update_agenda(message.id, AgendaModel::from_proto(message.agenda)?)
+                .update_agenda(message.id, agenda, &paths, &actor, "UpdateAgenda")
                 .await?;
             Ok::<Response<UpdateAgendaResponse>, Status>(Response::new(UpdateAgendaResponse {
                 agenda: Some(new_agenda.to_proto())
@@ -146,17 +178,229 @@ impl AgendaService for CustomAgendaService {
         })
     }
 
-    #[instrument(level = "info", target = "service::delete_agenda")]
+    #[instrument(level = "info", target = "service::delete_agenda", fields(success, error, warn))]
     async fn delete_agenda(
         &self,
         request: Request<DeleteAgendaRequest>,
     ) -> Result<Response<DeleteAgendaResponse>, Status> {
         trace_and_handle_error!({
+            let actor = actor_from_request(&request);
+            let id = request.into_inner().id;
             Arc::clone(&self.database)
                 .get_db_handler()
-                .delete_agenda(request.into_inner().id)
+                .delete_agenda(id, &actor, "DeleteAgenda")
                 .await?;
             Ok::<Response<DeleteAgendaResponse>, Status>(Response::new(DeleteAgendaResponse {}))
         })
     }
+
+    #[instrument(level = "info", target = "service::list_agenda_history", fields(success, error, warn))]
+    async fn list_agenda_history(
+        &self,
+        request: Request<ListAgendaHistoryRequest>,
+    ) -> Result<Response<ListAgendaHistoryResponse>, Status> {
+        trace_and_handle_error!({
+            let message :ListAgendaHistoryRequest = request.into_inner();
+            let (revisions, page, items) = Arc::clone(&self.database)
+                .get_db_handler()
+                .retrieve_agenda_history(message.id, message.page, message.items)
+                .await?;
+
+            Ok::<Response<ListAgendaHistoryResponse>, Status>(Response::new(ListAgendaHistoryResponse {
+                revisions: revisions.into_iter().map(|revision| revision.to_proto()).collect(),
+                next_page: page,
+                total: items,
+            }))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::restore_agenda", fields(success, error, warn))]
+    async fn restore_agenda(
+        &self,
+        request: Request<RestoreAgendaRequest>,
+    ) -> Result<Response<RestoreAgendaResponse>, Status> {
+        trace_and_handle_error!({
+            let actor = actor_from_request(&request);
+            let message :RestoreAgendaRequest = request.into_inner();
+            let database = Arc::clone(&self.database);
+            let revision: AuditModel = database
+                .get_db_handler()
+                .retrieve_agenda_revision(message.id, message.revision)
+                .await?;
+
+            let Some(agenda) = revision.after else {
+                return Err(Status::new(Code::InvalidArgument, format!("revision {} deleted the agenda and cannot be restored", message.revision)));
+            };
+            let new_agenda: AgendaModel = database
+                .get_db_handler()
+                .upsert_agenda(agenda, &actor, "RestoreAgenda")
+                .await?;
+
+            Ok::<Response<RestoreAgendaResponse>, Status>(Response::new(RestoreAgendaResponse {
+                agenda: Some(new_agenda.to_proto())
+            }))
+        })
+    }
+
+    type ExportAgendasStream = ReceiverStream<Result<FileChunk, Status>>;
+
+    #[instrument(level = "info", target = "service::export_agendas", fields(success, error, warn))]
+    async fn export_agendas(
+        &self,
+        request: Request<ExportAgendasRequest>,
+    ) -> Result<Response<Self::ExportAgendasStream>, Status> {
+        trace_and_handle_error!({
+            // The stream outlives the handler, it keeps the request slot and deadline until its end
+            let permit = request.extensions().get::<ConcurrencyPermit>().cloned();
+            let deadline = deadline::current();
+            let format = FileFormat::from_proto(request.into_inner().format).map_err(Status::invalid_argument)?;
+            let database = Arc::clone(&self.database);
+            let (sender, receiver) = tokio::sync::mpsc::channel(EXPORT_BUFFERED_CHUNKS);
+
+            // Pages are read as the client takes the chunks, from the replicas if the request may use them.
+            // A failure ends the stream with its status, a client going away ends the task.
+            tokio::spawn(with_read_from_replica(read_from_replica(), async move {
+                let _permit = permit;
+                let export = send_agendas(&database, format, &sender);
+                let result = match deadline {
+                    Some(deadline) => match tokio::time::timeout_at(deadline.into(), with_deadline(deadline, export)).await {
+                        Ok(result) => result,
+                        Err(_) => Err(Status::deadline_exceeded("deadline exceeded")),
+                    },
+                    None => export.await,
+                };
+                if let Err(status) = result {
+                    let _ = sender.send(Err(status)).await;
+                }
+            }));
+
+            Ok::<Response<Self::ExportAgendasStream>, Status>(Response::new(ReceiverStream::new(receiver)))
+        })
+    }
+
+    #[instrument(level = "info", target = "service::import_agendas", skip(request), fields(success, error, warn))]
+    async fn import_agendas(
+        &self,
+        request: Request<Streaming<ImportAgendasRequest>>,
+    ) -> Result<Response<ImportAgendasResponse>, Status> {
+        trace_and_handle_error!({
+            let actor = actor_from_request(&request);
+            let mut stream = request.into_inner();
+            let mut format = None;
+            let mut data = Vec::new();
+            while let Some(message) = stream.message().await? {
+                if format.is_none() {
+                    format = Some(FileFormat::from_proto(message.format).map_err(Status::invalid_argument)?);
+                }
+                if data.len() + message.data.len() > IMPORT_MAX_BYTES {
+                    return Err(Status::resource_exhausted(format!("the file is larger than {IMPORT_MAX_BYTES} bytes")));
+                }
+                data.extend_from_slice(&message.data);
+            }
+            let Some(format) = format else {
+                return Err(Status::invalid_argument("no file was sent"));
+            };
+            let records = read_agendas(format, &data).map_err(Status::invalid_argument)?;
+
+            // Every record is tried, like an agenda whose name is taken, and the failed ones are reported
+            let database = Arc::clone(&self.database);
+            let mut imported = 0;
+            let mut errors = Vec::new();
+            for (record, agenda) in records {
+                let result = match agenda {
+                    Ok(agenda) => database
+                        .get_db_handler()
+                        .create_agenda(agenda, &actor, "ImportAgendas")
+                        .await
+                        .map(|_| ())
+                        .map_err(|err| err.to_string()),
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(()) => imported += 1,
+                    Err(message) => errors.push(ImportError {record: record as i64, message}),
+                }
+            }
+
+            Ok::<Response<ImportAgendasResponse>, Status>(Response::new(ImportAgendasResponse {imported, errors}))
+        })
+    }
+}
+
+
+// Sends the agendas in pages of increasing ids, until the last one or until the client goes away
+async fn send_agendas(database: &DBLayers, format: FileFormat, sender: &Sender<Result<FileChunk, Status>>) -> Result<(), Status> {
+    let mut after_id = 0;
+    loop {
+        let agendas = database.get_db_handler().retrieve_after(after_id, EXPORT_PAGE_SIZE).await?;
+        // Ids start at 1, the CSV header goes before the first page even for an empty table
+        let data = write_agendas(format, &agendas, after_id == 0).map_err(Status::internal)?;
+        for chunk in data.chunks(EXPORT_CHUNK_SIZE) {
+            if sender.send(Ok(FileChunk {data: chunk.to_vec()})).await.is_err() {
+                return Ok(());
+            }
+        }
+        match agendas.last() {
+            Some(last) if agendas.len() as i64 == EXPORT_PAGE_SIZE => after_id = last.id,
+            _ => return Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use tonic::transport::{Channel, Server};
+    use tonic::transport::server::TcpIncoming;
+    use crate::agenda::FileFormat as ProtoFileFormat;
+    use crate::agenda::agenda_service_client::AgendaServiceClient;
+    use crate::agenda::agenda_service_server::AgendaServiceServer;
+    use super::*;
+
+    // Client streaming requests need a connection, so the service is served on a local port
+    async fn client() -> AgendaServiceClient<Channel> {
+        std::env::set_var("DATABASE_TYPE", "postgres");
+        let service = CustomAgendaService::new().await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(Server::builder()
+            .add_service(AgendaServiceServer::new(service))
+            .serve_with_incoming(incoming));
+        AgendaServiceClient::connect(endpoint).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_import_export_agendas() {
+        let mut client = client().await;
+        let name = format!("import-{}", uuid::Uuid::new_v4());
+        let file = format!("Full Name,E-mail Address,Mobile Phone\n{name},a@b.c,555\n,nameless@b.c,556\n{name},duplicate@b.c,557\n");
+
+        // Rows may be split across messages, only the first one needs the format
+        let (first, second) = file.as_bytes().split_at(50);
+        let messages = vec![
+            ImportAgendasRequest {format: ProtoFileFormat::Csv.into(), data: first.to_vec()},
+            ImportAgendasRequest {format: ProtoFileFormat::Unspecified.into(), data: second.to_vec()},
+        ];
+        let response = client.import_agendas(tokio_stream::iter(messages)).await.unwrap().into_inner();
+        assert_eq!(response.imported, 1);
+        assert_eq!(response.errors.iter().map(|error| error.record).collect::<Vec<i64>>(), vec![3, 4]);
+        assert_eq!(response.errors[0].message, "empty name");
+
+        let request = ExportAgendasRequest {format: ProtoFileFormat::Vcard4.into()};
+        let mut chunks = client.export_agendas(request).await.unwrap().into_inner();
+        let mut data = Vec::new();
+        while let Some(chunk) = chunks.message().await.unwrap() {
+            data.extend(chunk.data);
+        }
+        let exported = read_agendas(FileFormat::VCard4, &data).unwrap();
+        assert!(exported.iter().all(|(_, agenda)| agenda.is_ok()));
+        assert!(exported.iter().any(|(_, agenda)| agenda.as_ref().is_ok_and(|agenda| agenda.name == name && agenda.phone == "555")));
+
+        let messages = vec![ImportAgendasRequest {format: ProtoFileFormat::Unspecified.into(), data: file.into_bytes()}];
+        let status = client.import_agendas(tokio_stream::iter(messages)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        let status = client.export_agendas(ExportAgendasRequest {format: 42}).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
 }
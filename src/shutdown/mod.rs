@@ -0,0 +1,88 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{signal, SignalKind};
+
+
+// Kubernetes sends SIGTERM, a terminal sends SIGINT
+pub async fn shutdown_signal() -> &'static str {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM signal handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+// Time given to the database pool to close, connections still held by cancelled requests are abandoned
+pub const CLOSE_POOL_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn env_seconds(name: &str, default: u64) -> Duration {
+    let seconds = std::env::var(name)
+        .ok()
+        .and_then(|seconds| seconds.parse::<u64>().ok())
+        .unwrap_or(default);
+    Duration::from_secs(seconds)
+}
+
+// How long in-flight requests are given to complete once the servers stop accepting connections
+pub fn drain_deadline() -> Duration {
+    env_seconds("SHUTDOWN_DRAIN_TIMEOUT_SECS", 30)
+}
+
+// How long the health checks report NOT_SERVING before the servers stop accepting connections,
+// so load balancers stop routing to the instance first
+pub fn grace_period() -> Duration {
+    env_seconds("SHUTDOWN_GRACE_PERIOD_SECS", 5)
+}
+
+// Runs one step of the shutdown and logs its outcome, returning whether it succeeded
+pub async fn report_step<E, F>(step: &str, future: F) -> bool
+where
+    E: Display,
+    F: Future<Output = Result<(), E>>,
+{
+    let start = Instant::now();
+    match future.await {
+        Ok(()) => {
+            tracing::info!(step, elapsed_ms = start.elapsed().as_millis() as u64, "Shutdown step completed");
+            true
+        },
+        Err(err) => {
+            tracing::error!(step, elapsed_ms = start.elapsed().as_millis() as u64, "Shutdown step failed: {}", err);
+            false
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_report_step() {
+        assert!(report_step("ok", async { Ok::<(), String>(()) }).await);
+        assert!(!report_step("error", async { Err::<(), String>("failed".to_string()) }).await);
+    }
+
+    #[tokio::test]
+    async fn test_drain_deadline() {
+        assert_eq!(drain_deadline(), Duration::from_secs(30));
+
+        std::env::set_var("SHUTDOWN_DRAIN_TIMEOUT_SECS", "5");
+        assert_eq!(drain_deadline(), Duration::from_secs(5));
+
+        std::env::remove_var("SHUTDOWN_DRAIN_TIMEOUT_SECS");
+    }
+
+    #[tokio::test]
+    async fn test_grace_period() {
+        assert_eq!(grace_period(), Duration::from_secs(5));
+
+        std::env::set_var("SHUTDOWN_GRACE_PERIOD_SECS", "0");
+        assert_eq!(grace_period(), Duration::ZERO);
+
+        std::env::remove_var("SHUTDOWN_GRACE_PERIOD_SECS");
+    }
+}